@@ -1,25 +1,86 @@
 // specification: https://www.gamers.org/dEngine/quake/spec/quake-spec34/qkspec_4.htm
 // original: https://github.com/Thinkofname/rust-quake/blob/master/src/bsp/mod.rs
+use crate::entity::{entity_block, parse_vector3, EntityExt};
+use crate::helpers::{face_area, face_center, face_lightmap_size, face_vertices, world_faces};
 use crate::parse::*;
 use anyhow::{anyhow as e, Result};
 use bstr::ByteSlice;
-use cgmath::Vector3;
-use std::collections::HashMap;
-use std::io::{Read, Seek, SeekFrom};
+use cgmath::{InnerSpace, Vector3};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
 use std::ops::Range;
+use std::path::Path;
+#[cfg(feature = "rayon")]
+use std::path::PathBuf;
 
-pub(crate) const BSP_VERSION: i32 = 29;
 const SIZE_TEXTURE_INFO: usize = 4 * 6 + 4 * 2 + 4 * 2;
 const SIZE_VERTEX: usize = 4 * 3;
 const SIZE_EDGE: usize = 2 + 2;
 const SIZE_PLANE: usize = 4 * 3 + 4 + 4;
 const SIZE_FACE: usize = 2 + 2 + 4 + 2 + 2 + 4 + 4;
+const SIZE_NODE: usize = 4 + 2 * 2 + 2 * 3 * 2 + 2 + 2;
+const SIZE_LEAF: usize = 4 + 4 + 2 * 3 * 2 + 2 + 2 + 4;
 const SIZE_MODEL: usize = (4 * 3) * 3 + 4 * 4 + 4 + 4 + 4;
+const POINT_ENTITY_HALF_SIZE: f32 = 8.0;
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BspVersion {
+    /// Quake (version 29).
+    #[default]
+    Quake,
+    /// Half-Life (version 30), same lumps as Quake but with a palette embedded in each texture.
+    HalfLife,
+}
+
+impl TryFrom<i32> for BspVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(version: i32) -> Result<BspVersion> {
+        match version {
+            29 => Ok(BspVersion::Quake),
+            30 => Ok(BspVersion::HalfLife),
+            _ => Err(e!("Unsupported BSP version {version}, must be 29 or 30")),
+        }
+    }
+}
+
+impl BspVersion {
+    /// The version as stored in the first four bytes of the file.
+    pub fn version_magic(&self) -> [u8; 4] {
+        match self {
+            BspVersion::Quake => 29i32.to_le_bytes(),
+            BspVersion::HalfLife => 30i32.to_le_bytes(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Offset of the BSP within the reader, treated as offset 0 for all lump seeks.
+    pub base: u64,
+    /// Decode entity text through the Quake charset instead of requiring UTF-8, keeping
+    /// colored (high-bit) characters, gold digits and brackets readable.
+    pub quake_charset: bool,
+    /// Clamp lumps extending past the end of the data (e.g. a truncated file) and parse the
+    /// elements that are complete instead of returning an error. The missing elements are
+    /// silently dropped, so the result may reference e.g. models or faces that do not exist.
+    pub lenient: bool,
+}
 
 #[derive(Debug, Default, PartialEq)]
 pub struct BspFile {
+    pub version: BspVersion,
     pub entities: Vec<HashMap<String, String>>,
     pub planes: Vec<Plane>,
+    pub vertices: Vec<Vector3<f32>>,
+    pub visibility: Vec<u8>,
+    pub nodes: Vec<Node>,
+    pub leaves: Vec<Leaf>,
+    /// Face indexes referenced by the leaves.
+    pub face_list: Vec<u32>,
     pub textures: Vec<Texture>,
     pub texture_info: Vec<TextureInfo>,
     pub faces: Vec<Face>,
@@ -34,47 +95,84 @@ impl BspFile {
     where
         R: Read + Seek,
     {
+        BspFile::parse_at(r, 0)
+    }
+
+    /// Parse the file at `path`.
+    pub fn from_path<P>(path: P) -> Result<BspFile>
+    where
+        P: AsRef<Path>,
+    {
+        BspFile::parse(&mut BufReader::new(fs::File::open(path)?))
+    }
+
+    /// Parse a BSP held in memory.
+    pub fn from_bytes(bytes: &[u8]) -> Result<BspFile> {
+        BspFile::parse(&mut Cursor::new(bytes))
+    }
+
+    /// Parse a BSP embedded at `base` within a larger container (e.g. a PAK archive),
+    /// treating `base` as offset 0 for all lump seeks.
+    pub fn parse_at<R>(r: &mut R, base: u64) -> Result<BspFile>
+    where
+        R: Read + Seek,
+    {
+        let options = ParseOptions {
+            base,
+            ..ParseOptions::default()
+        };
+        BspFile::parse_with_options(r, &options)
+    }
+
+    /// Parse using the given options, see [`ParseOptions`].
+    pub fn parse_with_options<R>(r: &mut R, options: &ParseOptions) -> Result<BspFile>
+    where
+        R: Read + Seek,
+    {
+        let base = options.base;
+        let lump_limit = match options.lenient {
+            true => Some(r.seek(SeekFrom::End(0))?.saturating_sub(base)),
+            false => None,
+        };
+
         // 1. Model version
-        let version = r.read_long()?;
+        r.seek(SeekFrom::Start(base))?;
+        let version = BspVersion::try_from(r.read_long()?)?;
 
-        if version != BSP_VERSION {
-            return Err(e!("Unsupported BSP version, must be {BSP_VERSION}"));
-        }
-
-        let e_entities = Entry::read(r)?;
-        let e_planes = Entry::read(r)?;
-        let e_wall_textures = Entry::read(r)?;
-        let e_vertices = Entry::read(r)?;
-        let _e_visibility_list = Entry::read(r)?;
-        let _e_nodes = Entry::read(r)?;
-        let e_texture_info = Entry::read(r)?;
-        let e_faces = Entry::read(r)?;
-        let e_light_maps = Entry::read(r)?;
-        let _e_clip_nodes = Entry::read(r)?;
-        let _e_leaves = Entry::read(r)?;
-        let _e_face_list = Entry::read(r)?;
-        let e_edges = Entry::read(r)?;
-        let e_edge_list = Entry::read(r)?;
-        let e_models = Entry::read(r)?;
+        let e_entities = Entry::read(r, lump_limit)?;
+        let e_planes = Entry::read(r, lump_limit)?;
+        let e_wall_textures = Entry::read(r, lump_limit)?;
+        let e_vertices = Entry::read(r, lump_limit)?;
+        let e_visibility_list = Entry::read(r, lump_limit)?;
+        let e_nodes = Entry::read(r, lump_limit)?;
+        let e_texture_info = Entry::read(r, lump_limit)?;
+        let e_faces = Entry::read(r, lump_limit)?;
+        let e_light_maps = Entry::read(r, lump_limit)?;
+        let _e_clip_nodes = Entry::read(r, lump_limit)?;
+        let e_leaves = Entry::read(r, lump_limit)?;
+        let e_face_list = Entry::read(r, lump_limit)?;
+        let e_edges = Entry::read(r, lump_limit)?;
+        let e_edge_list = Entry::read(r, lump_limit)?;
+        let e_models = Entry::read(r, lump_limit)?;
 
         // 2. Entities
         let entities = {
             let mut entities_buf = vec![0; e_entities.size as usize];
-            r.seek(SeekFrom::Start(e_entities.offset as u64))?;
+            r.seek(SeekFrom::Start(base + e_entities.offset as u64))?;
             r.read_exact(&mut entities_buf)?;
-            parse_entities(&entities_buf)
+            parse_entities(&entities_buf, options.quake_charset)
         }?;
 
         // 3. Planes
-        r.seek(SeekFrom::Start(e_planes.offset as u64))?;
+        r.seek(SeekFrom::Start(base + e_planes.offset as u64))?;
         let planes = Plane::parse(e_planes.size as usize / SIZE_PLANE, r)?;
 
         // 4. Wall Textures
-        r.seek(SeekFrom::Start(e_wall_textures.offset as u64))?;
-        let textures = Texture::parse(r)?;
+        r.seek(SeekFrom::Start(base + e_wall_textures.offset as u64))?;
+        let textures = Texture::parse_with_version(r, version)?;
 
         // 5. Map Vertices
-        r.seek(SeekFrom::Start(e_vertices.offset as u64))?;
+        r.seek(SeekFrom::Start(base + e_vertices.offset as u64))?;
         let vertice_count = e_vertices.size as usize / SIZE_VERTEX;
         let mut vertices = Vec::with_capacity(vertice_count);
         for _ in 0..vertice_count {
@@ -82,45 +180,61 @@ impl BspFile {
         }
 
         // 5. Leaves Visibility lists.
+        let mut visibility = vec![0; e_visibility_list.size as usize];
+        r.seek(SeekFrom::Start(base + e_visibility_list.offset as u64))?;
+        r.read_exact(&mut visibility)?;
+
         // 6. Nodes
-        // (skipped)
+        r.seek(SeekFrom::Start(base + e_nodes.offset as u64))?;
+        let nodes = Node::parse(e_nodes.size as usize / SIZE_NODE, r)?;
 
         // 7. Texture Info
-        r.seek(SeekFrom::Start(e_texture_info.offset as u64))?;
+        r.seek(SeekFrom::Start(base + e_texture_info.offset as u64))?;
         let texture_info = TextureInfo::parse(e_texture_info.size as usize / SIZE_TEXTURE_INFO, r)?;
 
         // 8. Faces
-        r.seek(SeekFrom::Start(e_faces.offset as u64))?;
+        r.seek(SeekFrom::Start(base + e_faces.offset as u64))?;
         let faces = Face::parse(e_faces.size as usize / SIZE_FACE, r)?;
 
         // 9. Light Maps
         let mut light_maps = vec![0; e_light_maps.size as usize];
-        r.seek(SeekFrom::Start(e_light_maps.offset as u64))?;
+        r.seek(SeekFrom::Start(base + e_light_maps.offset as u64))?;
         r.read_exact(&mut light_maps)?;
 
         // 10. Clip Nodes
+        // (skipped)
+
         // 11. Leaves
+        r.seek(SeekFrom::Start(base + e_leaves.offset as u64))?;
+        let leaves = Leaf::parse(e_leaves.size as usize / SIZE_LEAF, r)?;
+
         // 12. Face List
-        // (skipped)
+        let face_list_count = e_face_list.size as usize / 2;
+        let mut face_list = Vec::with_capacity(face_list_count);
+        r.seek(SeekFrom::Start(base + e_face_list.offset as u64))?;
+        for _ in 0..face_list_count {
+            face_list.push(r.read_ushort()? as u32);
+        }
 
         // 13. Edges
-        r.seek(SeekFrom::Start(e_edges.offset as u64))?;
-        let edges = Edge::parse(e_edges.size as usize / SIZE_EDGE, vertices, r)?;
+        r.seek(SeekFrom::Start(base + e_edges.offset as u64))?;
+        let edges = Edge::parse(e_edges.size as usize / SIZE_EDGE, &vertices, r)?;
 
         // 14. Edge List
         let edge_list_count = e_edge_list.size as usize / 4;
         let mut edge_list = Vec::with_capacity(edge_list_count);
-        r.seek(SeekFrom::Start(e_edge_list.offset as u64))?;
+        r.seek(SeekFrom::Start(base + e_edge_list.offset as u64))?;
         for _ in 0..edge_list_count {
             edge_list.push(r.read_long()?);
         }
 
         // 15. Models
-        r.seek(SeekFrom::Start(e_models.offset as u64))?;
+        r.seek(SeekFrom::Start(base + e_models.offset as u64))?;
         let models = Model::parse(e_models.size as usize / SIZE_MODEL, r)?;
 
         // Done!
         Ok(BspFile {
+            version,
             entities,
             light_maps,
             textures,
@@ -128,303 +242,3397 @@ impl BspFile {
             edges,
             edge_list,
             planes,
+            vertices,
+            visibility,
+            nodes,
+            leaves,
+            face_list,
             faces,
             models,
         })
     }
-}
 
-fn parse_entities(bytes: &[u8]) -> Result<Vec<HashMap<String, String>>> {
-    let entities_str = bytes.to_str()?.to_string();
-    let mut entities = Vec::new();
-    let mut current_entity = HashMap::new();
+    /// Replace the entities, e.g. with those of an external `.ent` file.
+    pub fn with_entities(self, entities: Vec<HashMap<String, String>>) -> BspFile {
+        BspFile { entities, ..self }
+    }
 
-    for line in entities_str.lines() {
-        let line = line.trim();
+    /// Drop the lightmaps and the texture pixel data, keeping the geometry.
+    /// Texture names and sizes are kept so texture info indexes and texture coordinates stay valid.
+    pub fn geometry_only(self) -> BspFile {
+        let textures = self
+            .textures
+            .into_iter()
+            .map(|t| Texture {
+                pictures: Default::default(),
+                palette: None,
+                ..t
+            })
+            .collect();
 
-        if line == "{" {
-            current_entity = HashMap::new();
-        } else if line == "}" {
-            entities.push(current_entity.clone());
-        } else {
-            let (key, value) = line
-                .trim_matches('"')
-                .split_once("\" \"")
-                .unwrap_or_default();
-            current_entity.insert(key.to_string(), value.to_string());
+        BspFile {
+            textures,
+            light_maps: vec![],
+            ..self
         }
     }
-    Ok(entities)
-}
 
-#[derive(Debug, PartialEq)]
-pub struct Model {
-    pub bound: (Vector3<f32>, Vector3<f32>),
-    pub origin: Vector3<f32>,
-    pub face_indexes: Range<usize>,
-}
+    /// Whether the map has visibility data, i.e. was processed by vis.
+    pub fn has_vis_data(&self) -> bool {
+        !self.visibility.is_empty()
+    }
 
-impl Model {
-    pub fn parse<R>(count: usize, r: &mut R) -> Result<Vec<Model>>
-    where
-        R: Read + Seek,
-    {
-        let mut models = Vec::with_capacity(count);
+    /// Size of the pixel data of all textures, including mip levels.
+    pub fn texture_memory_bytes(&self) -> u64 {
+        self.textures
+            .iter()
+            .map(|t| {
+                let size = t.width as u64 * t.height as u64;
+                size + size / 4 + size / 16 + size / 64
+            })
+            .sum()
+    }
 
-        for _ in 0..count {
-            let bound_min = Vector3::from(r.read_vector3_float()?);
-            let bound_max = Vector3::from(r.read_vector3_float()?);
-            let origin = Vector3::from(r.read_vector3_float()?);
-            let _node_index = [
-                r.read_long()?,
-                r.read_long()?,
-                r.read_long()?,
-                r.read_long()?,
-            ];
-            let _leafs = r.read_long()?;
-            let face_start = r.read_long()?;
-            let face_number = r.read_long()?;
+    /// Texture indexes grouped by (width, height). Missing textures are left out.
+    pub fn textures_by_size(&self) -> BTreeMap<(u32, u32), Vec<usize>> {
+        let mut sizes: BTreeMap<(u32, u32), Vec<usize>> = BTreeMap::new();
 
-            models.push(Model {
-                bound: (bound_min, bound_max),
-                origin,
-                face_indexes: face_start as usize..(face_start as usize + face_number as usize),
-            });
+        for (index, texture) in self.textures.iter().enumerate() {
+            if texture.id != -1 {
+                sizes
+                    .entry((texture.width, texture.height))
+                    .or_default()
+                    .push(index);
+            }
         }
 
-        Ok(models)
+        sizes
     }
-}
-
-#[derive(Debug, Default, PartialEq, Eq)]
-pub struct Face {
-    pub plane_index: usize,
-    pub is_front: bool,
-    pub edge_indexes: Range<usize>,
-    pub texture_info_index: usize,
-    pub type_light: u8,
-    pub base_light: u8,
-    pub light: [u8; 2],
-    pub light_map: i32,
-}
 
-impl Face {
-    pub fn parse<R>(count: usize, r: &mut R) -> Result<Vec<Face>>
-    where
-        R: Read + Seek,
-    {
-        let mut faces = Vec::with_capacity(count);
+    /// Faces grouped by texture index, sorted by texture index.
+    /// Faces with an invalid texture info index are left out.
+    pub fn faces_grouped_by_texture(&self) -> Vec<(usize, Vec<&Face>)> {
+        let mut groups: BTreeMap<usize, Vec<&Face>> = BTreeMap::new();
 
-        for _ in 0..count {
-            faces.push(Face {
-                plane_index: r.read_ushort()? as usize,
-                is_front: r.read_ushort()? == 0,
-                edge_indexes: {
-                    let start = r.read_long()? as usize;
-                    start..(start + r.read_ushort()? as usize)
-                },
-                texture_info_index: r.read_ushort()? as usize,
-                type_light: r.read_uchar()?,
-                base_light: r.read_uchar()?,
-                light: [r.read_uchar()?, r.read_uchar()?],
-                light_map: r.read_long()?,
-            });
+        for face in &self.faces {
+            if let Some(info) = self.texture_info.get(face.texture_info_index) {
+                groups.entry(info.texture_index).or_default().push(face);
+            }
         }
 
-        Ok(faces)
+        groups.into_iter().collect()
     }
-}
 
-#[derive(Debug, PartialEq)]
-pub struct Plane {
-    pub normal: Vector3<f32>,
-    pub distance: f32,
-    pub kind: i32,
-}
+    /// Entities as key/value pairs borrowed from the stored strings, for read-only use without cloning.
+    pub fn entities_borrowed(&self) -> Vec<HashMap<&str, &str>> {
+        self.entities
+            .iter()
+            .map(|e| e.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect())
+            .collect()
+    }
 
-impl Plane {
-    pub fn parse<R>(count: usize, r: &mut R) -> Result<Vec<Plane>>
-    where
-        R: Read + Seek,
-    {
-        let mut planes = Vec::with_capacity(count);
+    /// Entities as a JSON array of objects. All values are kept as strings.
+    #[cfg(feature = "serde")]
+    pub fn entities_to_json(&self) -> String {
+        use serde_json::Value;
 
-        for _ in 0..count {
-            planes.push(Plane {
-                normal: Vector3::from(r.read_vector3_float()?),
-                distance: r.read_float()?,
-                kind: r.read_long()?,
-            });
-        }
+        let entities = self
+            .entities
+            .iter()
+            .map(|e| {
+                let pairs = e.iter().map(|(k, v)| (k.clone(), Value::String(v.clone())));
+                Value::Object(pairs.collect())
+            })
+            .collect();
+        Value::Array(entities).to_string()
+    }
 
-        Ok(planes)
+    /// The worldspawn entity, holding map-wide settings.
+    pub fn worldspawn(&self) -> Option<&HashMap<String, String>> {
+        self.entities
+            .iter()
+            .find(|e| e.classname() == Some("worldspawn"))
     }
-}
 
-#[derive(Debug, PartialEq)]
-pub struct Edge(pub Vector3<f32>, pub Vector3<f32>);
+    /// CD track to play, from the worldspawn `sounds` key.
+    pub fn cd_track(&self) -> Option<u32> {
+        self.worldspawn()?.value("sounds")?.trim().parse().ok()
+    }
 
-impl Edge {
-    pub fn parse<R>(count: usize, vertices: Vec<Vector3<f32>>, r: &mut R) -> Result<Vec<Edge>>
-    where
-        R: Read + Seek,
-    {
-        let mut edges = Vec::with_capacity(count);
-        for _ in 0..count {
-            edges.push(Edge(
-                vertices[r.read_ushort()? as usize],
-                vertices[r.read_ushort()? as usize],
-            ));
+    /// Theme of the map, from the worldspawn `worldtype` key.
+    pub fn worldtype(&self) -> Option<WorldType> {
+        match self.worldspawn()?.value("worldtype")?.trim() {
+            "0" => Some(WorldType::Medieval),
+            "1" => Some(WorldType::Runic),
+            "2" => Some(WorldType::Base),
+            _ => None,
         }
-        Ok(edges)
     }
-}
 
-#[derive(Debug, PartialEq)]
-pub struct TextureInfo {
-    pub vector_s: Vector3<f32>,
-    pub dist_s: f32,
-    pub vector_t: Vector3<f32>,
-    pub dist_t: f32,
-    pub texture_index: usize,
-    pub animated: bool,
-}
+    /// Editor or compiler that produced the map, based on worldspawn keys left by the tools.
+    pub fn compiler_hint(&self) -> Option<String> {
+        let worldspawn = self.worldspawn()?;
 
-impl TextureInfo {
-    pub fn parse<R>(count: usize, r: &mut R) -> Result<Vec<TextureInfo>>
-    where
-        R: Read + Seek,
-    {
-        let mut info = Vec::with_capacity(count);
+        if let Some(generator) = worldspawn.value("_generator") {
+            return Some(generator.to_string());
+        }
 
-        for _ in 0..count {
-            let vector_s = Vector3::from(r.read_vector3_float()?);
-            let dist_s = r.read_float()?;
-            let vector_t = Vector3::from(r.read_vector3_float()?);
-            let dist_t = r.read_float()?;
-            let texture_index = r.read_ulong()? as usize;
-            let animated = r.read_ulong()? != 0;
+        match worldspawn.value("_tb_mod") {
+            Some(tb_mod) => Some(format!("TrenchBroom ({tb_mod})")),
+            None if worldspawn.keys().any(|k| k.starts_with("_tb_")) => {
+                Some("TrenchBroom".to_string())
+            }
+            None => None,
+        }
+    }
 
-            info.push(TextureInfo {
-                vector_s,
-                dist_s,
-                vector_t,
-                dist_t,
-                texture_index,
-                animated,
+    /// Sun light from the ericw-tools worldspawn keys, `None` if `_sunlight` is not set.
+    pub fn sun_light(&self) -> Option<SunLight> {
+        let worldspawn = self.worldspawn()?;
+        let intensity = worldspawn.float("_sunlight")?;
+
+        let mangle = worldspawn
+            .value("_sun_mangle")
+            .or(worldspawn.value("_sunlight_mangle"))
+            .and_then(parse_vector3)
+            .unwrap_or(Vector3::new(0.0, -90.0, 0.0));
+        let (yaw, pitch) = (mangle.x.to_radians(), mangle.y.to_radians());
+        let direction = Vector3::new(
+            yaw.cos() * pitch.cos(),
+            yaw.sin() * pitch.cos(),
+            pitch.sin(),
+        );
+
+        let color = worldspawn
+            .value("_sunlight_color")
+            .and_then(parse_vector3)
+            .map(|c| match c.x > 1.0 || c.y > 1.0 || c.z > 1.0 {
+                true => c / 255.0,
+                false => c,
             })
-        }
+            .unwrap_or(Vector3::new(1.0, 1.0, 1.0));
 
-        Ok(info)
+        Some(SunLight {
+            intensity,
+            direction,
+            color,
+        })
     }
-}
 
-#[derive(Debug, Default, PartialEq, Eq)]
-pub struct Texture {
-    pub id: i32,
-    pub name: String,
-    pub width: u32,
-    pub height: u32,
-    pub pictures: [Picture; 4],
-}
+    /// Global minimum light level, from the worldspawn `_minlight` key.
+    pub fn min_light(&self) -> f32 {
+        self.worldspawn()
+            .and_then(|w| w.float("_minlight"))
+            .unwrap_or_default()
+    }
 
-impl Texture {
-    pub fn parse<R>(r: &mut R) -> Result<Vec<Texture>>
+    /// Entities as indented entity lump text with stable key order:
+    /// `classname` first, then `origin`, then the remaining keys sorted.
+    pub fn entities_pretty(&self) -> String {
+        self.entities
+            .iter()
+            .map(|e| entity_block(e, "    "))
+            .collect()
+    }
+
+    /// Texture indexes of the main animation cycles (`+0name` to `+9name`), one chain per base name,
+    /// sorted by frame.
+    pub fn animation_chains(&self) -> Vec<Vec<usize>> {
+        self.texture_chains(|frame| frame.is_ascii_digit())
+    }
+
+    /// Texture indexes of the alternate animation cycles (`+aname` to `+jname`), used when triggered.
+    pub fn animation_alt_chains(&self) -> Vec<Vec<usize>> {
+        self.texture_chains(|frame| ('a'..='j').contains(&frame.to_ascii_lowercase()))
+    }
+
+    fn texture_chains<F>(&self, is_frame: F) -> Vec<Vec<usize>>
     where
-        R: Read + Seek,
+        F: Fn(char) -> bool,
     {
-        let base_offset = r.stream_position()?;
-        let count = r.read_long()?;
+        let mut chains: Vec<(&str, Vec<usize>)> = Vec::new();
 
-        let mut textures = Vec::with_capacity(count as usize);
-        for id in 0..count {
-            let offset = r.read_long()?;
-            if offset == -1 {
-                textures.push(Texture {
-                    id: -1,
-                    ..Texture::default()
-                });
+        for (index, texture) in self.textures.iter().enumerate() {
+            let mut chars = texture.name.chars();
+            let (Some('+'), Some(frame)) = (chars.next(), chars.next()) else {
+                continue;
+            };
+            if !is_frame(frame) {
                 continue;
             }
 
-            let coffset = r.stream_position()?;
+            let base_name = chars.as_str();
+            match chains
+                .iter_mut()
+                .find(|(name, _)| name.eq_ignore_ascii_case(base_name))
+            {
+                Some((_, chain)) => chain.push(index),
+                None => chains.push((base_name, vec![index])),
+            }
+        }
 
-            r.seek(SeekFrom::Start(base_offset + offset as u64))?;
-            let name = from_cstring(&read_string!(r, 16))?;
-            let width = r.read_ulong()?;
-            let height = r.read_ulong()?;
-            let offsets = [
-                r.read_ulong()?,
-                r.read_ulong()?,
-                r.read_ulong()?,
-                r.read_ulong()?,
-            ];
+        // frames are sorted by their frame character, the texture order is not guaranteed
+        let frame_of =
+            |index: &usize| self.textures[*index].name.as_bytes()[1].to_ascii_lowercase();
+        chains
+            .into_iter()
+            .map(|(_, mut chain)| {
+                chain.sort_by_key(frame_of);
+                chain
+            })
+            .collect()
+    }
 
-            let mut tex = Texture {
-                id,
-                name,
-                width,
-                height,
-                pictures: [
-                    Picture::default(),
-                    Picture::default(),
-                    Picture::default(),
-                    Picture::default(),
-                ],
+    /// Name of the texture covering the largest area of the world faces, and that area.
+    pub fn dominant_texture(&self) -> Option<(String, f32)> {
+        let mut areas: HashMap<&str, f32> = HashMap::new();
+
+        for face in world_faces(self) {
+            let Some(texture) = self
+                .texture_info
+                .get(face.texture_info_index)
+                .and_then(|info| self.textures.get(info.texture_index))
+                .filter(|t| !t.name.is_empty())
+            else {
+                continue;
             };
+            *areas.entry(&texture.name).or_default() += face_area(self, face);
+        }
 
-            for (i, o) in offsets.into_iter().enumerate() {
-                r.seek(SeekFrom::Start(base_offset + offset as u64 + o as u64))?;
-                let w = width >> i;
-                let h = height >> i;
-                let mut data = vec![0; (w * h) as usize];
-                r.read_exact(&mut data)?;
-                tex.pictures[i] = Picture {
-                    width: w,
-                    height: h,
-                    data,
-                };
-            }
+        areas
+            .into_iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(a.0)))
+            .map(|(name, area)| (name.to_string(), area))
+    }
 
-            textures.push(tex);
-            r.seek(SeekFrom::Start(coffset))?;
+    /// Maps this map links to through `trigger_changelevel` entities.
+    pub fn level_transitions(&self) -> Vec<String> {
+        let mut maps: Vec<String> = Vec::new();
+
+        for entity in &self.entities {
+            if entity.classname() != Some("trigger_changelevel") {
+                continue;
+            }
+            if let Some(map) = entity.value("map") {
+                if !maps.iter().any(|m| m == map) {
+                    maps.push(map.to_string());
+                }
+            }
         }
 
-        Ok(textures)
+        maps
     }
-}
 
-#[derive(Debug, Default, PartialEq, Eq)]
-pub struct Picture {
-    pub width: u32,
-    pub height: u32,
-    pub data: Vec<u8>,
-}
+    /// Entity indexes and `target`/`killtarget` values without a matching `targetname` in the map.
+    pub fn dangling_targets(&self) -> Vec<(usize, String)> {
+        let targetnames: HashSet<&str> = self
+            .entities
+            .iter()
+            .filter_map(|e| e.value("targetname"))
+            .collect();
 
-#[derive(Debug, Default, PartialEq, Eq)]
-struct Entry {
-    offset: i32,
-    size: i32,
-}
+        let mut dangling = Vec::new();
+        for (index, entity) in self.entities.iter().enumerate() {
+            for key in ["target", "killtarget"] {
+                if let Some(target) = entity.value(key) {
+                    if !targetnames.contains(target) {
+                        dangling.push((index, target.to_string()));
+                    }
+                }
+            }
+        }
 
-impl Entry {
-    fn read<R>(r: &mut R) -> Result<Entry>
-    where
-        R: Read,
-    {
-        Ok(Entry {
-            offset: r.read_long()?,
-            size: r.read_long()?,
+        dangling
+    }
+
+    /// Distinct sound files referenced by `noise*` keys, file paths in `sounds` keys and ambient sound
+    /// entities, in order of appearance. Numeric `sounds` values select built-in sounds and are skipped.
+    pub fn sound_references(&self) -> Vec<String> {
+        let mut sounds: Vec<String> = Vec::new();
+        let mut add = |sound: &str| {
+            if !sounds.iter().any(|s| s == sound) {
+                sounds.push(sound.to_string());
+            }
+        };
+
+        for entity in &self.entities {
+            if let Some(sound) = entity.classname().and_then(ambient_sound) {
+                add(sound);
+            }
+
+            let mut keys: Vec<&String> = entity
+                .keys()
+                .filter(|k| k.starts_with("noise") || k.as_str() == "sounds")
+                .collect();
+            keys.sort();
+            for key in keys {
+                let value = &entity[key];
+                if !value.is_empty() && value.parse::<f32>().is_err() {
+                    add(value);
+                }
+            }
+        }
+
+        sounds
+    }
+
+    /// Light entities with a `targetname`, i.e. lights toggled by triggers.
+    pub fn switchable_lights(&self) -> Vec<SwitchableLight> {
+        self.entities
+            .iter()
+            .filter(|e| e.classname().is_some_and(|c| c.starts_with("light")))
+            .filter_map(|e| {
+                Some(SwitchableLight {
+                    origin: e.origin()?,
+                    targetname: e.value("targetname")?.to_string(),
+                    style: e.float("style").unwrap_or_default() as u8,
+                })
+            })
+            .collect()
+    }
+
+    /// Navigation graph of `waypoint` entities, with nodes keyed by `waypointnumber` and directed
+    /// edges from the `wp0` to `wp7` links. Links to unknown waypoints are skipped.
+    pub fn waypoint_graph(&self) -> WaypointGraph {
+        let waypoints: Vec<(u32, &HashMap<String, String>)> = self
+            .entities
+            .iter()
+            .filter(|e| e.classname() == Some("waypoint"))
+            .filter_map(|e| Some((e.value("waypointnumber")?.trim().parse().ok()?, e)))
+            .collect();
+
+        let nodes: BTreeMap<u32, Vector3<f32>> = waypoints
+            .iter()
+            .filter_map(|(number, e)| Some((*number, e.origin()?)))
+            .collect();
+
+        let mut edges = Vec::new();
+        for (number, entity) in &waypoints {
+            for i in 0..8 {
+                let Some(target) = entity
+                    .value(&format!("wp{i}"))
+                    .and_then(|v| v.trim().parse::<u32>().ok())
+                else {
+                    continue;
+                };
+                if nodes.contains_key(number) && nodes.contains_key(&target) {
+                    edges.push((*number, target));
+                }
+            }
+        }
+
+        WaypointGraph { nodes, edges }
+    }
+
+    /// Average origin of all `info_player_*` spawn points.
+    pub fn spawn_centroid(&self) -> Option<Vector3<f32>> {
+        let origins: Vec<Vector3<f32>> = self.spawn_points().map(|(_, o)| o).collect();
+
+        if origins.is_empty() {
+            return None;
+        }
+
+        Some(origins.iter().sum::<Vector3<f32>>() / origins.len() as f32)
+    }
+
+    /// Origins of `info_player_*` spawn points grouped by classname.
+    pub fn spawns_by_type(&self) -> HashMap<String, Vec<Vector3<f32>>> {
+        let mut spawns: HashMap<String, Vec<Vector3<f32>>> = HashMap::new();
+
+        for (classname, origin) in self.spawn_points() {
+            spawns
+                .entry(classname.to_string())
+                .or_default()
+                .push(origin);
+        }
+
+        spawns
+    }
+
+    fn spawn_points(&self) -> impl Iterator<Item = (&str, Vector3<f32>)> {
+        self.entities.iter().filter_map(|e| {
+            let classname = e.classname().filter(|c| c.starts_with("info_player_"))?;
+            Some((classname, e.origin()?))
+        })
+    }
+
+    /// World space bounds of an entity: the bounds of its submodel offset by its origin for
+    /// brush entities, or a small box around the origin for point entities.
+    pub fn entity_world_bounds(&self, entity: &HashMap<String, String>) -> Option<BoundingBox> {
+        let origin = entity.origin();
+
+        match entity.submodel_index() {
+            Some(index) => {
+                let (min, max) = self.models.get(index)?.bound;
+                let offset = origin.unwrap_or(Vector3::new(0.0, 0.0, 0.0));
+                Some(BoundingBox::new(min + offset, max + offset))
+            }
+            None => {
+                let half_size = Vector3::new(1.0, 1.0, 1.0) * POINT_ENTITY_HALF_SIZE;
+                origin.map(|o| BoundingBox::new(o - half_size, o + half_size))
+            }
+        }
+    }
+
+    /// Brush model (submodel) of an entity.
+    pub fn entity_model(&self, entity: &HashMap<String, String>) -> Option<&Model> {
+        self.models.get(entity.submodel_index()?)
+    }
+
+    /// Index of the entity owning the submodel at `model_index`, i.e. the entity with `"model" "*N"`.
+    pub fn submodel_owner(&self, model_index: usize) -> Option<usize> {
+        self.entities
+            .iter()
+            .position(|e| e.submodel_index() == Some(model_index))
+    }
+
+    /// Faces of a model.
+    pub fn model_faces(&self, model: &Model) -> &[Face] {
+        self.faces
+            .get(model.face_indexes.clone())
+            .unwrap_or_default()
+    }
+
+    /// Faces of the submodel of a `trigger_*` or `func_*` entity.
+    pub fn brush_faces<'a>(&'a self, entity: &HashMap<String, String>) -> Option<Vec<&'a Face>> {
+        let classname = entity.classname()?;
+        if !classname.starts_with("trigger_") && !classname.starts_with("func_") {
+            return None;
+        }
+
+        let model = self.entity_model(entity)?;
+        Some(self.model_faces(model).iter().collect())
+    }
+
+    /// Leaves with the given contents.
+    pub fn leaves_with_content(&self, content: LeafContents) -> Vec<&Leaf> {
+        self.leaves
+            .iter()
+            .filter(|l| l.contents == content)
+            .collect()
+    }
+
+    /// Total number of face list entries (marksurfaces) referenced by leaves.
+    pub fn total_marksurfaces(&self) -> u32 {
+        self.leaves.iter().map(Leaf::face_count).sum()
+    }
+
+    /// Index of the leaf containing `point`, walking the world BSP tree from its head node.
+    pub fn leaf_at(&self, point: Vector3<f32>) -> Option<usize> {
+        let mut node_index = u32::try_from(self.models.first()?.node_indexes[0]).ok()?;
+
+        // a valid tree visits each node at most once
+        for _ in 0..=self.nodes.len() {
+            let node = self.nodes.get(node_index as usize)?;
+            let plane = self.planes.get(node.plane_index)?;
+            let side = match plane.normal.dot(point) - plane.distance >= 0.0 {
+                true => 0,
+                false => 1,
+            };
+
+            match node.child(side) {
+                NodeChild::Node(index) => node_index = index,
+                NodeChild::Leaf(index) => return Some(index as usize),
+            }
+        }
+
+        None
+    }
+
+    /// World faces ordered front to back as seen from `eye`, visiting the near side
+    /// of each node first.
+    pub fn faces_front_to_back(&self, eye: Vector3<f32>) -> impl Iterator<Item = &Face> {
+        enum Step {
+            Node(u32),
+            Faces(Range<usize>),
+        }
+
+        let mut faces = Vec::new();
+        let mut stack = Vec::new();
+        let mut visited = 0;
+
+        if let Some(model) = self.models.first() {
+            if let Ok(head) = u32::try_from(model.node_indexes[0]) {
+                stack.push(Step::Node(head));
+            }
+        }
+
+        while let Some(step) = stack.pop() {
+            let node_index = match step {
+                Step::Faces(range) => {
+                    faces.extend(self.faces.get(range).unwrap_or_default());
+                    continue;
+                }
+                Step::Node(index) => index,
+            };
+
+            // a valid tree visits each node once
+            visited += 1;
+            if visited > self.nodes.len() {
+                break;
+            }
+            let Some(node) = self.nodes.get(node_index as usize) else {
+                continue;
+            };
+            let Some(plane) = self.planes.get(node.plane_index) else {
+                continue;
+            };
+            let near = match plane.normal.dot(eye) - plane.distance >= 0.0 {
+                true => 0,
+                false => 1,
+            };
+
+            if let NodeChild::Node(far) = node.child(1 - near) {
+                stack.push(Step::Node(far));
+            }
+            stack.push(Step::Faces(node.face_indexes.clone()));
+            if let NodeChild::Node(near) = node.child(near) {
+                stack.push(Step::Node(near));
+            }
+        }
+
+        faces.into_iter()
+    }
+
+    /// Whether `point` is inside a solid leaf. Points outside the tree are solid.
+    pub fn point_is_solid(&self, point: Vector3<f32>) -> bool {
+        match self.leaf_at(point).and_then(|i| self.leaves.get(i)) {
+            Some(leaf) => leaf.contents == LeafContents::Solid,
+            None => true,
+        }
+    }
+
+    /// Bounds of the world model (model 0).
+    pub fn world_bounds(&self) -> Option<BoundingBox> {
+        let (min, max) = self.models.first()?.bound;
+        Some(BoundingBox::new(min, max))
+    }
+
+    /// Bounds of all models, indexed like [`BspFile::models`].
+    pub fn model_bounds(&self) -> Vec<BoundingBox> {
+        self.models
+            .iter()
+            .map(|m| BoundingBox::new(m.bound.0, m.bound.1))
+            .collect()
+    }
+
+    /// World surface area and volume, and their ratio as a rough measure of detail.
+    pub fn complexity_metrics(&self) -> ComplexityMetrics {
+        let surface_area: f32 = world_faces(self).iter().map(|f| face_area(self, f)).sum();
+        let volume = self.world_bounds().map(|b| b.volume()).unwrap_or_default();
+
+        ComplexityMetrics {
+            surface_area,
+            volume,
+            surface_to_volume: match volume > 0.0 {
+                true => surface_area / volume,
+                false => 0.0,
+            },
+        }
+    }
+
+    /// Face indexes with the distance from `from` to the face centroid, nearest first.
+    pub fn faces_by_distance(&self, from: Vector3<f32>) -> Vec<(usize, f32)> {
+        let mut distances: Vec<(usize, f32)> = self
+            .faces
+            .iter()
+            .enumerate()
+            .filter_map(|(i, face)| Some((i, (face_center(self, face)? - from).magnitude())))
+            .collect();
+        distances.sort_by(|a, b| a.1.total_cmp(&b.1));
+        distances
+    }
+
+    /// Nearest world face hit by a ray and the distance to the hit, by testing the triangles
+    /// of every world face (Möller–Trumbore).
+    pub fn raycast_faces(&self, origin: Vector3<f32>, dir: Vector3<f32>) -> Option<(usize, f32)> {
+        const EPSILON: f32 = 1e-6;
+
+        if dir.magnitude2() < EPSILON {
+            return None;
+        }
+        let dir = dir.normalize();
+        let first_index = self.models.first().map_or(0, |m| m.face_indexes.start);
+
+        let mut nearest: Option<(usize, f32)> = None;
+        for (i, face) in world_faces(self).iter().enumerate() {
+            let vertices = face_vertices(self, face);
+            let Some(&v0) = vertices.first() else {
+                continue;
+            };
+
+            for w in vertices[1..].windows(2) {
+                let (edge1, edge2) = (w[0] - v0, w[1] - v0);
+                let p = dir.cross(edge2);
+                let det = edge1.dot(p);
+                if det.abs() < EPSILON {
+                    continue;
+                }
+
+                let t_vec = origin - v0;
+                let u = t_vec.dot(p) / det;
+                if !(0.0..=1.0).contains(&u) {
+                    continue;
+                }
+                let q = t_vec.cross(edge1);
+                let v = dir.dot(q) / det;
+                if v < 0.0 || u + v > 1.0 {
+                    continue;
+                }
+
+                let t = edge2.dot(q) / det;
+                if t > EPSILON && nearest.is_none_or(|(_, d)| t < d) {
+                    nearest = Some((first_index + i, t));
+                }
+            }
+        }
+
+        nearest
+    }
+
+    /// Number of faces on each plane, by plane index.
+    pub fn plane_face_fanout(&self) -> HashMap<usize, usize> {
+        let mut fanout = HashMap::new();
+        for face in &self.faces {
+            *fanout.entry(face.plane_index).or_default() += 1;
+        }
+        fanout
+    }
+
+    /// Indexes of the faces sharing at least one edge with the face at `face_index`.
+    pub fn adjacent_faces(&self, face_index: usize) -> Vec<usize> {
+        let Some(face) = self.faces.get(face_index) else {
+            return vec![];
+        };
+        let edges: HashSet<usize> = self.face_edges(face).collect();
+
+        (0..self.faces.len())
+            .filter(|&i| i != face_index)
+            .filter(|&i| self.face_edges(&self.faces[i]).any(|e| edges.contains(&e)))
+            .collect()
+    }
+
+    /// Placement of each face lightmap in 128x128 blocks, using the first fit allocation of
+    /// GLQuake's `AllocBlock` (without its limit of 64 blocks).
+    pub fn lightmap_allocation(&self) -> Vec<LightmapRect> {
+        const BLOCK_SIZE: u32 = 128;
+
+        let mut rects = Vec::new();
+        let mut blocks: Vec<[u32; BLOCK_SIZE as usize]> = Vec::new();
+
+        for (face_index, face) in self.faces.iter().enumerate() {
+            let Some((width, height)) = face_lightmap_size(self, face) else {
+                continue;
+            };
+            // GLQuake never places a lightmap spanning the full block width
+            if width >= BLOCK_SIZE || height > BLOCK_SIZE {
+                continue;
+            }
+
+            let mut block = 0;
+            loop {
+                if block == blocks.len() {
+                    blocks.push([0; BLOCK_SIZE as usize]);
+                }
+                let allocated = &mut blocks[block];
+
+                // leftmost of the lowest positions where the lightmap fits horizontally
+                let best = (0..BLOCK_SIZE - width)
+                    .map(|x| {
+                        let columns = &allocated[x as usize..(x + width) as usize];
+                        (columns.iter().copied().max().unwrap_or_default(), x)
+                    })
+                    .min();
+
+                match best {
+                    Some((y, x)) if y + height <= BLOCK_SIZE => {
+                        allocated[x as usize..(x + width) as usize].fill(y + height);
+                        rects.push(LightmapRect {
+                            face_index,
+                            block,
+                            x,
+                            y,
+                            width,
+                            height,
+                        });
+                        break;
+                    }
+                    _ => block += 1,
+                }
+            }
+        }
+
+        rects
+    }
+
+    /// Faces grouped into connected components of faces sharing edges.
+    pub fn geometry_islands(&self) -> Vec<Vec<usize>> {
+        fn find(parents: &mut [usize], i: usize) -> usize {
+            let mut root = i;
+            while parents[root] != root {
+                root = parents[root];
+            }
+            parents[i] = root;
+            root
+        }
+
+        let mut parents: Vec<usize> = (0..self.faces.len()).collect();
+        let mut edge_owners: HashMap<usize, usize> = HashMap::new();
+
+        for (face_index, face) in self.faces.iter().enumerate() {
+            for edge in self.face_edges(face) {
+                let owner = *edge_owners.entry(edge).or_insert(face_index);
+                let (a, b) = (find(&mut parents, owner), find(&mut parents, face_index));
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+
+        let mut islands: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for i in 0..self.faces.len() {
+            islands.entry(find(&mut parents, i)).or_default().push(i);
+        }
+        islands.into_values().collect()
+    }
+
+    /// Edge indexes of a face, regardless of direction.
+    fn face_edges<'a>(&'a self, face: &Face) -> impl Iterator<Item = usize> + 'a {
+        self.edge_list
+            .get(face.edge_indexes.clone())
+            .unwrap_or_default()
+            .iter()
+            .map(|i| i.unsigned_abs() as usize)
+    }
+
+    /// Edges of the edge list as (start, end) vertex positions, with negative entries reversed.
+    pub fn directed_edges(&self) -> impl Iterator<Item = (Vector3<f32>, Vector3<f32>)> + '_ {
+        self.edge_list
+            .iter()
+            .filter_map(|&index| self.directed_edge(index))
+    }
+
+    /// Edge referenced by an edge list entry, reversed if the entry is negative.
+    pub(crate) fn directed_edge(&self, index: i32) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let edge = self.edges.get(index.unsigned_abs() as usize)?;
+
+        match index >= 0 {
+            true => Some((edge.0, edge.1)),
+            false => Some((edge.1, edge.0)),
+        }
+    }
+
+    /// Check that every edge list entry references an existing edge.
+    /// Vertex references of the edges are checked when parsing.
+    pub fn verify_edge_references(&self) -> Result<()> {
+        for (i, &index) in self.edge_list.iter().enumerate() {
+            let edge_index = index.unsigned_abs() as usize;
+
+            if edge_index >= self.edges.len() {
+                return Err(e!(
+                    "Edge list entry {i} references edge {index}, but there are only {} edges",
+                    self.edges.len()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that every texture info references an existing texture.
+    pub fn verify_texture_indices(&self) -> Result<()> {
+        for (i, info) in self.texture_info.iter().enumerate() {
+            if info.texture_index >= self.textures.len() {
+                return Err(e!(
+                    "Texture info {i} references texture {}, but there are only {} textures",
+                    info.texture_index,
+                    self.textures.len()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compare the lumps of `header` with the parsed elements, and check that the lumps
+    /// neither overlap nor leave gaps between them.
+    pub fn lump_integrity_report(&self, header: &BspHeader) -> Vec<LumpIssue> {
+        let mut issues = Vec::new();
+
+        let counts = [
+            (1, SIZE_PLANE, self.planes.len()),
+            (3, SIZE_VERTEX, self.vertices.len()),
+            (4, 1, self.visibility.len()),
+            (5, SIZE_NODE, self.nodes.len()),
+            (6, SIZE_TEXTURE_INFO, self.texture_info.len()),
+            (7, SIZE_FACE, self.faces.len()),
+            (8, 1, self.light_maps.len()),
+            (10, SIZE_LEAF, self.leaves.len()),
+            (11, 2, self.face_list.len()),
+            (12, SIZE_EDGE, self.edges.len()),
+            (13, 4, self.edge_list.len()),
+            (14, SIZE_MODEL, self.models.len()),
+        ];
+        for (lump, element_size, actual) in counts {
+            let expected = header.lumps[lump].size.max(0) as usize / element_size;
+            if expected != actual {
+                issues.push(LumpIssue::CountMismatch {
+                    lump,
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        let mut lumps: Vec<(usize, &Entry)> = header
+            .lumps
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.size > 0)
+            .collect();
+        lumps.sort_by_key(|(_, e)| e.offset);
+
+        let header_size = 4 + 8 * header.lumps.len() as u64;
+        let mut previous: Option<usize> = None;
+        let mut end = header_size;
+        for (lump, entry) in lumps {
+            let offset = entry.offset.max(0) as u64;
+
+            if offset < end {
+                if let Some(first) = previous {
+                    issues.push(LumpIssue::Overlap {
+                        first,
+                        second: lump,
+                    });
+                }
+            } else if offset > end.next_multiple_of(4) {
+                issues.push(LumpIssue::Gap {
+                    before: lump,
+                    size: offset - end,
+                });
+            }
+
+            let lump_end = offset + entry.size as u64;
+            if lump_end >= end {
+                previous = Some(lump);
+                end = lump_end;
+            }
+        }
+
+        issues
+    }
+
+    /// Indexes of faces with a missing texture info or texture, or a texture without a name.
+    pub fn faces_without_texture(&self) -> Vec<usize> {
+        self.faces
+            .iter()
+            .enumerate()
+            .filter(|(_, face)| {
+                self.texture_info
+                    .get(face.texture_info_index)
+                    .and_then(|info| self.textures.get(info.texture_index))
+                    .is_none_or(|t| t.name.is_empty())
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Names of textures whose pixels are not embedded, to be loaded from external WAD files.
+    pub fn externally_referenced_textures(&self) -> Vec<String> {
+        self.textures
+            .iter()
+            .filter(|t| t.id != -1 && t.pictures[0].data.is_empty())
+            .map(|t| t.name.clone())
+            .collect()
+    }
+
+    /// Remove textures not referenced by any texture info and remap the texture indexes.
+    /// Animated textures are kept with all frames of their main and alternate cycles.
+    /// Returns the number of removed textures.
+    pub fn prune_unused_textures(&mut self) -> usize {
+        let mut used: HashSet<usize> = self.texture_info.iter().map(|i| i.texture_index).collect();
+
+        // one chain per base name, holding both the main and the alternate frames
+        let chains = self.texture_chains(|frame| {
+            frame.is_ascii_digit() || ('a'..='j').contains(&frame.to_ascii_lowercase())
+        });
+        for chain in chains {
+            if chain.iter().any(|index| used.contains(index)) {
+                used.extend(chain);
+            }
+        }
+        let mut remap = vec![None; self.textures.len()];
+        let mut kept = Vec::with_capacity(used.len());
+
+        for (index, texture) in self.textures.drain(..).enumerate() {
+            if used.contains(&index) {
+                remap[index] = Some(kept.len());
+                kept.push(texture);
+            }
+        }
+
+        for (id, texture) in kept.iter_mut().enumerate() {
+            if texture.id != -1 {
+                texture.id = id as i32;
+            }
+        }
+
+        for info in self.texture_info.iter_mut() {
+            if let Some(Some(index)) = remap.get(info.texture_index) {
+                info.texture_index = *index;
+            }
+        }
+
+        let removed = remap.len() - kept.len();
+        self.textures = kept;
+        removed
+    }
+
+    /// Deterministic FNV-1a hash of the geometry (planes, vertices, edges, edge list and faces),
+    /// ignoring entities, textures and lightmaps.
+    pub fn geometry_fingerprint(&self) -> u64 {
+        let mut bytes = Vec::new();
+        let push_vector = |bytes: &mut Vec<u8>, v: &Vector3<f32>| {
+            for c in [v.x, v.y, v.z] {
+                bytes.extend_from_slice(&c.to_le_bytes());
+            }
+        };
+
+        for plane in &self.planes {
+            push_vector(&mut bytes, &plane.normal);
+            bytes.extend_from_slice(&plane.distance.to_le_bytes());
+            bytes.extend_from_slice(&plane.kind.to_le_bytes());
+        }
+        for vertex in &self.vertices {
+            push_vector(&mut bytes, vertex);
+        }
+        for edge in &self.edges {
+            push_vector(&mut bytes, &edge.0);
+            push_vector(&mut bytes, &edge.1);
+        }
+        for index in &self.edge_list {
+            bytes.extend_from_slice(&index.to_le_bytes());
+        }
+        for face in &self.faces {
+            for value in [
+                face.plane_index,
+                face.is_front as usize,
+                face.edge_indexes.start,
+                face.edge_indexes.end,
+                face.texture_info_index,
+            ] {
+                bytes.extend_from_slice(&(value as u64).to_le_bytes());
+            }
+        }
+
+        bytes.iter().fold(FNV_OFFSET_BASIS, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(FNV_PRIME)
         })
     }
+
+    /// Merge vertices that are within `epsilon` of each other.
+    /// Returns the welded vertices and a table mapping each original vertex index to its welded index.
+    pub fn weld_vertices(&self, epsilon: f32) -> (Vec<Vector3<f32>>, Vec<u32>) {
+        let cell_size = epsilon.max(f32::EPSILON);
+        let cell_of = |v: &Vector3<f32>| {
+            (
+                (v.x / cell_size).floor() as i64,
+                (v.y / cell_size).floor() as i64,
+                (v.z / cell_size).floor() as i64,
+            )
+        };
+
+        let mut welded: Vec<Vector3<f32>> = Vec::new();
+        let mut remap = Vec::with_capacity(self.vertices.len());
+        let mut cells: HashMap<(i64, i64, i64), Vec<u32>> = HashMap::new();
+
+        for vertex in &self.vertices {
+            let (cx, cy, cz) = cell_of(vertex);
+            let neighbours = (-1..=1).flat_map(|x| {
+                (-1..=1).flat_map(move |y| (-1..=1).map(move |z| (cx + x, cy + y, cz + z)))
+            });
+
+            let existing = neighbours
+                .filter_map(|cell| cells.get(&cell))
+                .flatten()
+                .find(|&&i| (welded[i as usize] - vertex).magnitude() <= epsilon)
+                .copied();
+
+            let index = existing.unwrap_or_else(|| {
+                let index = welded.len() as u32;
+                welded.push(*vertex);
+                cells.entry((cx, cy, cz)).or_default().push(index);
+                index
+            });
+            remap.push(index);
+        }
+
+        (welded, remap)
+    }
+
+    /// Edges as deduplicated `(min, max)` vertex index pairs. Edges only keep vertex
+    /// positions, so indexes refer to the vertices returned by `weld_vertices(0.0)`.
+    pub fn unique_edge_indices(&self) -> Vec<(u32, u32)> {
+        let (_, remap) = self.weld_vertices(0.0);
+        let index_of: HashMap<[u32; 3], u32> = self
+            .vertices
+            .iter()
+            .zip(remap)
+            .map(|(v, i)| ([v.x.to_bits(), v.y.to_bits(), v.z.to_bits()], i))
+            .collect();
+        let index = |v: &Vector3<f32>| index_of.get(&[v.x.to_bits(), v.y.to_bits(), v.z.to_bits()]);
+
+        let mut seen = HashSet::new();
+        self.edges
+            .iter()
+            .filter_map(|e| {
+                let (a, b) = (*index(&e.0)?, *index(&e.1)?);
+                Some((a.min(b), a.max(b)))
+            })
+            .filter(|pair| seen.insert(*pair))
+            .collect()
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anyhow::Result;
-    use pretty_assertions::assert_eq;
-    use std::fs;
+/// Parse a BSP held in a [`bytes::Bytes`] buffer without copying it.
+#[cfg(feature = "bytes")]
+pub fn parse_bytes_buf(b: bytes::Bytes) -> Result<BspFile> {
+    BspFile::parse(&mut std::io::Cursor::new(b))
+}
+
+/// Parse all `.bsp` files in a directory concurrently, sorted by path.
+/// A file failing to parse does not affect the others.
+#[cfg(feature = "rayon")]
+pub fn parse_dir<P>(dir: P) -> Vec<(PathBuf, Result<BspFile>)>
+where
+    P: AsRef<Path>,
+{
+    use rayon::prelude::*;
+
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("bsp"))
+            })
+            .collect(),
+        Err(_) => return vec![],
+    };
+    paths.sort();
+
+    paths
+        .into_par_iter()
+        .map(|path| {
+            let result = fs::File::open(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|file| BspFile::parse(&mut BufReader::new(file)));
+            (path, result)
+        })
+        .collect()
+}
+
+/// Load the entities of an external `.ent` file, which engines use to override a map's entity lump.
+pub fn load_entities_file<R>(r: &mut R) -> Result<Vec<HashMap<String, String>>>
+where
+    R: Read,
+{
+    let mut buf = Vec::new();
+    r.read_to_end(&mut buf)?;
+    parse_entities(&buf, false)
+}
+
+/// Sound played by an ambient sound entity.
+fn ambient_sound(classname: &str) -> Option<&'static str> {
+    match classname {
+        "ambient_comp_hum" => Some("ambience/comp1.wav"),
+        "ambient_drip" => Some("ambience/drip1.wav"),
+        "ambient_drone" => Some("ambience/drone6.wav"),
+        "ambient_flouro_buzz" => Some("ambience/buzz1.wav"),
+        "ambient_light_buzz" => Some("ambience/fl_hum1.wav"),
+        "ambient_suck_wind" => Some("ambience/suck1.wav"),
+        "ambient_swamp1" => Some("ambience/swamp1.wav"),
+        "ambient_swamp2" => Some("ambience/swamp2.wav"),
+        "ambient_thunder" => Some("ambience/thunder1.wav"),
+        _ => None,
+    }
+}
+
+/// Entities of all maps concatenated, each tagged with the index of its map in `maps`
+/// as `"_source_index"`. Only entities are merged, not geometry.
+pub fn merge_entities(maps: &[BspFile]) -> Vec<HashMap<String, String>> {
+    maps.iter()
+        .enumerate()
+        .flat_map(|(index, bsp)| {
+            bsp.entities.iter().map(move |entity| {
+                let mut entity = entity.clone();
+                entity.insert("_source_index".to_string(), index.to_string());
+                entity
+            })
+        })
+        .collect()
+}
+
+/// Parse the entities of a `.map` source file, ignoring brush definitions.
+pub fn parse_map_source<R>(r: &mut R) -> Result<Vec<HashMap<String, String>>>
+where
+    R: Read,
+{
+    load_entities_file(r)
+}
+
+/// Readable character of a byte in the Quake charset. The upper half repeats the lower half
+/// in another color, so colored text decodes to its plain glyphs.
+fn quake_char(b: u8) -> char {
+    match b {
+        b'\t' | b'\n' | b'\r' | 0x20..=0x7e => char::from(b),
+        // a colored quote must not end the quoted entity value
+        0xa2 => '\u{201d}',
+        0xa0..=0xfe => char::from(b - 0x80),
+        _ => match b & 0x7f {
+            0x10 => '[',
+            0x11 => ']',
+            c @ 0x12..=0x1b => char::from(b'0' + c - 0x12),
+            0x00 | 0x05 | 0x0e | 0x0f | 0x1c => '•',
+            0x1d => '◄',
+            0x1e => '═',
+            0x1f => '►',
+            _ => ' ',
+        },
+    }
+}
+
+fn parse_entities(bytes: &[u8], quake_charset: bool) -> Result<Vec<HashMap<String, String>>> {
+    let entities_str = match quake_charset {
+        true => bytes.iter().map(|&b| quake_char(b)).collect(),
+        false => bytes.to_str()?.to_string(),
+    };
+    let mut entities = Vec::new();
+    let mut current_entity = HashMap::new();
+    let mut depth = 0u32;
+
+    // brushes of .map sources are nested blocks (depth 2) and are skipped
+    for line in entities_str.lines() {
+        let line = line.trim();
+
+        if line == "{" {
+            if depth == 0 {
+                current_entity = HashMap::new();
+            }
+            depth += 1;
+        } else if line == "}" {
+            // a stray closing brace outside any entity is ignored
+            if depth == 0 {
+                continue;
+            }
+            depth -= 1;
+            if depth == 0 {
+                entities.push(current_entity.clone());
+            }
+        } else if depth == 1 && !line.starts_with("//") {
+            let (key, value) = line
+                .trim_matches('"')
+                .split_once("\" \"")
+                .unwrap_or_default();
+            current_entity.insert(key.to_string(), value.to_string());
+        }
+    }
+    Ok(entities)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl BoundingBox {
+    pub fn new(min: Vector3<f32>, max: Vector3<f32>) -> BoundingBox {
+        BoundingBox { min, max }
+    }
+
+    pub fn contains(&self, point: Vector3<f32>) -> bool {
+        (0..3).all(|i| self.min[i] <= point[i] && point[i] <= self.max[i])
+    }
+
+    pub fn volume(&self) -> f32 {
+        let size = self.max - self.min;
+        size.x.max(0.0) * size.y.max(0.0) * size.z.max(0.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LightmapRect {
+    pub face_index: usize,
+    /// Index of the 128x128 lightmap block.
+    pub block: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Theme of a map, deciding e.g. which key models are used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WorldType {
+    Medieval,
+    /// Also known as metal.
+    Runic,
+    Base,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SunLight {
+    pub intensity: f32,
+    /// Direction the light travels in.
+    pub direction: Vector3<f32>,
+    /// RGB color from 0 to 1.
+    pub color: Vector3<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwitchableLight {
+    pub origin: Vector3<f32>,
+    pub targetname: String,
+    /// Light style, 0 when not set.
+    pub style: u8,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WaypointGraph {
+    /// Waypoint origins by waypoint number.
+    pub nodes: BTreeMap<u32, Vector3<f32>>,
+    /// Directed links as (from, to) waypoint numbers.
+    pub edges: Vec<(u32, u32)>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ComplexityMetrics {
+    /// Total area of the world faces.
+    pub surface_area: f32,
+    /// Volume of the world bounds.
+    pub volume: f32,
+    pub surface_to_volume: f32,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Model {
+    pub bound: (Vector3<f32>, Vector3<f32>),
+    pub origin: Vector3<f32>,
+    pub node_indexes: [i32; 4],
+    /// Number of leaves with visibility data, excluding the shared solid leaf 0.
+    pub vis_leaf_count: usize,
+    pub face_indexes: Range<usize>,
+}
+
+impl Model {
+    pub fn parse<R>(count: usize, r: &mut R) -> Result<Vec<Model>>
+    where
+        R: Read + Seek,
+    {
+        let mut models = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let bound_min = Vector3::from(r.read_vector3_float()?);
+            let bound_max = Vector3::from(r.read_vector3_float()?);
+            let origin = Vector3::from(r.read_vector3_float()?);
+            let node_indexes = [
+                r.read_long()?,
+                r.read_long()?,
+                r.read_long()?,
+                r.read_long()?,
+            ];
+            let vis_leaf_count = r.read_long()? as usize;
+            let face_start = r.read_long()?;
+            let face_number = r.read_long()?;
+
+            models.push(Model {
+                bound: (bound_min, bound_max),
+                origin,
+                node_indexes,
+                vis_leaf_count,
+                face_indexes: face_start as usize..(face_start as usize + face_number as usize),
+            });
+        }
+
+        Ok(models)
+    }
+
+    /// Head node of collision hull 0-3: hull 0 is the BSP tree, hulls 1-3 are clip node trees.
+    pub fn hull_headnode(&self, hull: usize) -> Option<i32> {
+        self.node_indexes.get(hull).copied()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Node {
+    pub plane_index: usize,
+    /// Front and back children: node indexes if positive, otherwise `-(leaf index + 1)`.
+    pub children: [i32; 2],
+    pub bounds: BoundingBox,
+    pub face_indexes: Range<usize>,
+}
+
+impl Node {
+    pub fn parse<R>(count: usize, r: &mut R) -> Result<Vec<Node>>
+    where
+        R: Read + Seek,
+    {
+        let mut nodes = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let plane_index = r.read_long()? as usize;
+            let children = [r.read_short()? as i32, r.read_short()? as i32];
+            let min = Vector3::from(r.read_vector3_short()?).map(f32::from);
+            let max = Vector3::from(r.read_vector3_short()?).map(f32::from);
+            let face_start = r.read_ushort()? as usize;
+            let face_count = r.read_ushort()? as usize;
+
+            nodes.push(Node {
+                plane_index,
+                children,
+                bounds: BoundingBox::new(min, max),
+                face_indexes: face_start..(face_start + face_count),
+            });
+        }
+
+        Ok(nodes)
+    }
+
+    /// Child on the front (0) or back (1) side of the node plane.
+    /// Panics if `side` is not 0 or 1.
+    pub fn child(&self, side: usize) -> NodeChild {
+        match self.children[side] {
+            c if c >= 0 => NodeChild::Node(c as u32),
+            c => NodeChild::Leaf((-(c + 1)) as u32),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeChild {
+    Node(u32),
+    Leaf(u32),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Leaf {
+    pub contents: LeafContents,
+    /// Offset of the leaf in the visibility lump, -1 if it has no visibility data.
+    pub vis_offset: i32,
+    pub bounds: BoundingBox,
+    /// Indexes into the face list.
+    pub face_list_indexes: Range<usize>,
+    /// Volume of the water, sky, slime and lava ambient sounds.
+    pub ambient_levels: [u8; 4],
+}
+
+impl Leaf {
+    pub fn parse<R>(count: usize, r: &mut R) -> Result<Vec<Leaf>>
+    where
+        R: Read + Seek,
+    {
+        let mut leaves = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let contents = LeafContents::from(r.read_long()?);
+            let vis_offset = r.read_long()?;
+            let min = Vector3::from(r.read_vector3_short()?).map(f32::from);
+            let max = Vector3::from(r.read_vector3_short()?).map(f32::from);
+            let face_start = r.read_ushort()? as usize;
+            let face_count = r.read_ushort()? as usize;
+            let ambient_levels = [
+                r.read_uchar()?,
+                r.read_uchar()?,
+                r.read_uchar()?,
+                r.read_uchar()?,
+            ];
+
+            leaves.push(Leaf {
+                contents,
+                vis_offset,
+                bounds: BoundingBox::new(min, max),
+                face_list_indexes: face_start..(face_start + face_count),
+                ambient_levels,
+            });
+        }
+
+        Ok(leaves)
+    }
+
+    /// Number of face list entries (marksurfaces) of the leaf.
+    pub fn face_count(&self) -> u32 {
+        self.face_list_indexes.len() as u32
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeafContents {
+    Empty,
+    Solid,
+    Water,
+    Slime,
+    Lava,
+    Sky,
+    Unknown(i32),
+}
+
+impl From<i32> for LeafContents {
+    fn from(value: i32) -> LeafContents {
+        match value {
+            -1 => LeafContents::Empty,
+            -2 => LeafContents::Solid,
+            -3 => LeafContents::Water,
+            -4 => LeafContents::Slime,
+            -5 => LeafContents::Lava,
+            -6 => LeafContents::Sky,
+            v => LeafContents::Unknown(v),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Face {
+    pub plane_index: usize,
+    pub is_front: bool,
+    pub edge_indexes: Range<usize>,
+    pub texture_info_index: usize,
+    pub type_light: u8,
+    pub base_light: u8,
+    pub light: [u8; 2],
+    pub light_map: i32,
+}
+
+impl Face {
+    pub fn parse<R>(count: usize, r: &mut R) -> Result<Vec<Face>>
+    where
+        R: Read + Seek,
+    {
+        let mut faces = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            faces.push(Face {
+                plane_index: r.read_ushort()? as usize,
+                is_front: r.read_ushort()? == 0,
+                edge_indexes: {
+                    let start = r.read_long()? as usize;
+                    start..(start + r.read_ushort()? as usize)
+                },
+                texture_info_index: r.read_ushort()? as usize,
+                type_light: r.read_uchar()?,
+                base_light: r.read_uchar()?,
+                light: [r.read_uchar()?, r.read_uchar()?],
+                light_map: r.read_long()?,
+            });
+        }
+
+        Ok(faces)
+    }
+
+    /// Faces without a lightmap (sky, liquids, fullbright) have an offset of -1.
+    pub fn has_lightmap(&self) -> bool {
+        self.light_map >= 0
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+    pub kind: i32,
+}
+
+impl Plane {
+    pub fn parse<R>(count: usize, r: &mut R) -> Result<Vec<Plane>>
+    where
+        R: Read + Seek,
+    {
+        let mut planes = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            planes.push(Plane {
+                normal: Vector3::from(r.read_vector3_float()?),
+                distance: r.read_float()?,
+                kind: r.read_long()?,
+            });
+        }
+
+        Ok(planes)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Edge(pub Vector3<f32>, pub Vector3<f32>);
+
+impl Edge {
+    pub fn parse<R>(count: usize, vertices: &[Vector3<f32>], r: &mut R) -> Result<Vec<Edge>>
+    where
+        R: Read + Seek,
+    {
+        let vertex = |r: &mut R| -> Result<Vector3<f32>> {
+            let index = r.read_ushort()? as usize;
+            vertices.get(index).copied().ok_or_else(|| {
+                e!(
+                    "Edge references vertex {index}, but there are only {} vertices",
+                    vertices.len()
+                )
+            })
+        };
+
+        let mut edges = Vec::with_capacity(count);
+        for _ in 0..count {
+            edges.push(Edge(vertex(r)?, vertex(r)?));
+        }
+        Ok(edges)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TextureInfo {
+    pub vector_s: Vector3<f32>,
+    pub dist_s: f32,
+    pub vector_t: Vector3<f32>,
+    pub dist_t: f32,
+    pub texture_index: usize,
+    pub animated: bool,
+}
+
+impl TextureInfo {
+    pub fn parse<R>(count: usize, r: &mut R) -> Result<Vec<TextureInfo>>
+    where
+        R: Read + Seek,
+    {
+        let mut info = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let vector_s = Vector3::from(r.read_vector3_float()?);
+            let dist_s = r.read_float()?;
+            let vector_t = Vector3::from(r.read_vector3_float()?);
+            let dist_t = r.read_float()?;
+            let texture_index = r.read_ulong()? as usize;
+            let animated = r.read_ulong()? != 0;
+
+            info.push(TextureInfo {
+                vector_s,
+                dist_s,
+                vector_t,
+                dist_t,
+                texture_index,
+                animated,
+            })
+        }
+
+        Ok(info)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Texture {
+    pub id: i32,
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub pictures: [Picture; 4],
+    /// RGB colors of the palette embedded in Half-Life textures.
+    pub palette: Option<Vec<u8>>,
+}
+
+impl Texture {
+    pub fn parse<R>(r: &mut R) -> Result<Vec<Texture>>
+    where
+        R: Read + Seek,
+    {
+        Texture::parse_with_version(r, BspVersion::Quake)
+    }
+
+    /// Parse the textures of a map of the given version, see [`BspVersion`].
+    pub fn parse_with_version<R>(r: &mut R, version: BspVersion) -> Result<Vec<Texture>>
+    where
+        R: Read + Seek,
+    {
+        let base_offset = r.stream_position()?;
+        let stream_len = r.seek(SeekFrom::End(0))?;
+        r.seek(SeekFrom::Start(base_offset))?;
+        let count = r.read_long()?;
+
+        let mut textures = Vec::with_capacity(count as usize);
+        for id in 0..count {
+            let offset = r.read_long()?;
+            let coffset = r.stream_position()?;
+            let texture = Texture::read_at(r, version, base_offset, id, offset, stream_len)?;
+            textures.push(texture);
+            r.seek(SeekFrom::Start(coffset))?;
+        }
+
+        Ok(textures)
+    }
+
+    /// Read the texture at `offset` within the texture lump at `base_offset`.
+    fn read_at<R>(
+        r: &mut R,
+        version: BspVersion,
+        base_offset: u64,
+        id: i32,
+        offset: i32,
+        stream_len: u64,
+    ) -> Result<Texture>
+    where
+        R: Read + Seek,
+    {
+        if offset == -1 {
+            return Ok(Texture {
+                id: -1,
+                ..Texture::default()
+            });
+        }
+
+        r.seek(SeekFrom::Start(base_offset + offset as u64))?;
+        let name = from_cstring(&read_string!(r, 16))?;
+        let width = r.read_ulong()?;
+        let height = r.read_ulong()?;
+        let offsets = [
+            r.read_ulong()?,
+            r.read_ulong()?,
+            r.read_ulong()?,
+            r.read_ulong()?,
+        ];
+
+        let mut tex = Texture {
+            id,
+            name,
+            width,
+            height,
+            pictures: [
+                Picture::default(),
+                Picture::default(),
+                Picture::default(),
+                Picture::default(),
+            ],
+            palette: None,
+        };
+
+        // textures stored in external WAD files have no mip offsets
+        if offsets[0] == 0 {
+            return Ok(tex);
+        }
+
+        for (i, o) in offsets.into_iter().enumerate() {
+            let mip_offset = base_offset + offset as u64 + o as u64;
+            let w = width >> i;
+            let h = height >> i;
+
+            if mip_offset + w as u64 * h as u64 > stream_len {
+                return Err(e!("Mip level {i} of texture {} is out of bounds", tex.name));
+            }
+
+            r.seek(SeekFrom::Start(mip_offset))?;
+            let mut data = vec![0; (w * h) as usize];
+            r.read_exact(&mut data)?;
+            tex.pictures[i] = Picture {
+                width: w,
+                height: h,
+                data,
+            };
+        }
+
+        if version == BspVersion::HalfLife {
+            tex.palette = Some(hl_texture_palette(r, &tex)?.to_vec());
+        }
+
+        Ok(tex)
+    }
+
+    /// RGB pixels of a mip level using the embedded palette, `None` without a palette (Quake textures).
+    pub fn to_rgb(&self, level: usize) -> Option<Vec<u8>> {
+        let palette = self.palette.as_ref()?;
+        let picture = self.pictures.get(level)?;
+
+        let mut rgb = Vec::with_capacity(picture.data.len() * 3);
+        for &index in &picture.data {
+            let color = palette.get(index as usize * 3..index as usize * 3 + 3)?;
+            rgb.extend_from_slice(color);
+        }
+        Some(rgb)
+    }
+
+    /// Content type derived from the texture naming conventions.
+    pub fn texture_content(&self) -> ContentType {
+        let name = self.name.to_ascii_lowercase();
+
+        match name.as_str() {
+            "clip" => ContentType::Clip,
+            "trigger" => ContentType::Trigger,
+            n if n.starts_with("sky") => ContentType::Sky,
+            n if n.starts_with('*') && n.contains("lava") => ContentType::Lava,
+            n if n.starts_with('*') && n.contains("slime") => ContentType::Slime,
+            n if n.starts_with('*') => ContentType::Water,
+            _ => ContentType::Solid,
+        }
+    }
+
+    /// The largest mip level with no dimension above `max_dim`, or the smallest mip level if none fit.
+    pub fn downsampled(&self, max_dim: u32) -> &Picture {
+        self.pictures
+            .iter()
+            .find(|p| p.width.max(p.height) <= max_dim)
+            .unwrap_or(&self.pictures[3])
+    }
+}
+
+/// Read the palette embedded in a Half-Life texture, with `r` positioned right after the
+/// smallest mip level of `texture`: a color count (always 256) followed by the RGB colors.
+/// Parsed version 30 textures keep it in [`Texture::palette`], used by [`Texture::to_rgb`].
+pub fn hl_texture_palette<R>(r: &mut R, texture: &Texture) -> Result<[u8; 768]>
+where
+    R: Read + Seek,
+{
+    let color_count = r.read_ushort()?;
+    if color_count != 256 {
+        return Err(e!(
+            "Palette of texture {} has {color_count} colors, expected 256",
+            texture.name
+        ));
+    }
+
+    let mut palette = [0; 768];
+    r.read_exact(&mut palette)
+        .map_err(|_| e!("Palette of texture {} is out of bounds", texture.name))?;
+    Ok(palette)
+}
+
+/// Read only the texture names of the texture lump at `base_offset`, without reading any pixels.
+/// Missing textures are returned as empty names to keep the indexes aligned with [`BspFile::textures`].
+pub fn texture_names<R>(r: &mut R, base_offset: u64) -> Result<Vec<String>>
+where
+    R: Read + Seek,
+{
+    r.seek(SeekFrom::Start(base_offset))?;
+    let count = r.read_long()?;
+    // the count is not trusted for the capacity, a corrupt count fails on the first missing offset
+    let mut offsets = Vec::new();
+    for _ in 0..count {
+        offsets.push(r.read_long()?);
+    }
+
+    let mut names = Vec::with_capacity(offsets.len());
+    for offset in offsets {
+        if offset == -1 {
+            names.push(String::new());
+            continue;
+        }
+        r.seek(SeekFrom::Start(base_offset + offset as u64))?;
+        names.push(from_cstring(&read_string!(r, 16))?);
+    }
+
+    Ok(names)
+}
+
+/// Textures of a memory mapped BSP file, decoded on first access.
+#[cfg(feature = "mmap")]
+pub struct LazyTextures {
+    mmap: memmap2::Mmap,
+    version: BspVersion,
+    base_offset: u64,
+    offsets: Vec<i32>,
+    textures: Vec<std::cell::OnceCell<Texture>>,
+}
+
+#[cfg(feature = "mmap")]
+impl LazyTextures {
+    /// Memory map the file and read the texture directory, without decoding any textures.
+    pub fn open<P>(path: P) -> Result<LazyTextures>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: the map is read-only, but the file must not be modified while it is mapped.
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        let mut r = std::io::Cursor::new(&mmap[..]);
+
+        let version = BspVersion::try_from(r.read_long()?)?;
+
+        let _e_entities = Entry::read(&mut r, None)?;
+        let _e_planes = Entry::read(&mut r, None)?;
+        let e_wall_textures = Entry::read(&mut r, None)?;
+
+        let base_offset = e_wall_textures.offset as u64;
+        r.seek(SeekFrom::Start(base_offset))?;
+        let count = r.read_long()?;
+        if count < 0 || 4 + count as u64 * 4 > e_wall_textures.size as u64 {
+            return Err(e!(
+                "Texture count {count} does not fit the texture lump of {} bytes",
+                e_wall_textures.size
+            ));
+        }
+        let mut offsets = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            offsets.push(r.read_long()?);
+        }
+
+        let textures = offsets.iter().map(|_| std::cell::OnceCell::new()).collect();
+        Ok(LazyTextures {
+            mmap,
+            version,
+            base_offset,
+            offsets,
+            textures,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Texture at `index`, decoded on the first call.
+    pub fn get(&self, index: usize) -> Result<&Texture> {
+        let (Some(&offset), Some(cell)) = (self.offsets.get(index), self.textures.get(index))
+        else {
+            return Err(e!(
+                "Texture {index} does not exist, there are {} textures",
+                self.len()
+            ));
+        };
+
+        if let Some(texture) = cell.get() {
+            return Ok(texture);
+        }
+
+        let mut r = std::io::Cursor::new(&self.mmap[..]);
+        let stream_len = self.mmap.len() as u64;
+        let texture = Texture::read_at(
+            &mut r,
+            self.version,
+            self.base_offset,
+            index as i32,
+            offset,
+            stream_len,
+        )?;
+        Ok(cell.get_or_init(|| texture))
+    }
+
+    /// Whether the texture at `index` has been decoded.
+    pub fn is_decoded(&self, index: usize) -> bool {
+        self.textures.get(index).is_some_and(|t| t.get().is_some())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentType {
+    Solid,
+    Water,
+    Lava,
+    Slime,
+    Sky,
+    Clip,
+    Trigger,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Picture {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Version and lump entries at the start of a BSP file. The lumps are in file order:
+/// entities, planes, textures, vertices, visibility, nodes, texture info, faces, lightmaps,
+/// clip nodes, leaves, face list, edges, edge list and models.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BspHeader {
+    pub version: i32,
+    pub lumps: [Entry; 15],
+}
+
+impl BspHeader {
+    /// Read the header at the current position.
+    pub fn read<R>(r: &mut R) -> Result<BspHeader>
+    where
+        R: Read,
+    {
+        let version = r.read_long()?;
+        let mut lumps: [Entry; 15] = Default::default();
+        for lump in lumps.iter_mut() {
+            *lump = Entry::read(r, None)?;
+        }
+
+        Ok(BspHeader { version, lumps })
+    }
+}
+
+/// Problem found by [`BspFile::lump_integrity_report`], lumps are referenced by their header index.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LumpIssue {
+    /// The lump size does not match the number of parsed elements.
+    CountMismatch {
+        lump: usize,
+        expected: usize,
+        actual: usize,
+    },
+    Overlap {
+        first: usize,
+        second: usize,
+    },
+    /// Unused bytes before a lump, beyond the 4 byte alignment padding.
+    Gap {
+        before: usize,
+        size: u64,
+    },
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Entry {
+    pub offset: i32,
+    pub size: i32,
+}
+
+impl Entry {
+    /// Read a lump entry, clamping its size to end at `limit` if given.
+    fn read<R>(r: &mut R, limit: Option<u64>) -> Result<Entry>
+    where
+        R: Read,
+    {
+        let offset = r.read_long()?;
+        let mut size = r.read_long()?;
+
+        if let Some(limit) = limit {
+            let available = limit.saturating_sub(offset.max(0) as u64);
+            size = size.min(available.min(i32::MAX as u64) as i32);
+        }
+
+        Ok(Entry { offset, size })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::face_normal;
+    use anyhow::Result;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_parse_at() -> Result<()> {
+        let data = fs::read("tests/files/povdmm4.bsp")?;
+        let mut buf = vec![0xff; 1234];
+        buf.extend_from_slice(&data);
+
+        let bsp = BspFile::parse_at(&mut Cursor::new(buf), 1234)?;
+        assert_eq!(bsp, BspFile::parse(&mut Cursor::new(data))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_weld_vertices() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let (welded, remap) = bsp.weld_vertices(0.01);
+
+        assert!(welded.len() <= bsp.vertices.len());
+        assert_eq!(remap.len(), bsp.vertices.len());
+        for (vertex, &index) in bsp.vertices.iter().zip(&remap) {
+            assert!((welded[index as usize] - vertex).magnitude() <= 0.01);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_edge_indices() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let (welded, _) = bsp.weld_vertices(0.0);
+        let pairs = bsp.unique_edge_indices();
+
+        assert!(!pairs.is_empty());
+        assert!(pairs.len() <= bsp.edges.len());
+        assert_eq!(pairs.iter().collect::<HashSet<_>>().len(), pairs.len());
+        assert!(pairs
+            .iter()
+            .all(|&(a, b)| a <= b && (b as usize) < welded.len()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_entities_file() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let entities = load_entities_file(&mut fs::File::open("tests/files/povdmm4.ent")?)?;
+        assert_eq!(entities.len(), 5);
+        assert_eq!(
+            entities[0].get("message"),
+            Some(&"DMM4 Arena (ent override)".to_string())
+        );
+
+        let face_count = bsp.faces.len();
+        let bsp = bsp.with_entities(entities.clone());
+        assert_eq!(bsp.entities, entities);
+        assert_eq!(bsp.faces.len(), face_count);
+        Ok(())
+    }
+
+    #[test]
+    fn test_texture_memory_bytes() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let pixel_count: usize = bsp
+            .textures
+            .iter()
+            .flat_map(|t| t.pictures.iter().map(|p| p.data.len()))
+            .sum();
+
+        assert!(bsp.texture_memory_bytes() > 0);
+        assert_eq!(bsp.texture_memory_bytes(), pixel_count as u64);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_entities_stray_brace() -> Result<()> {
+        let lump = b"{\n\"classname\" \"worldspawn\"\n}\n}\n{\n\"classname\" \"light\"\n}\n";
+        let entities = parse_entities(lump, false)?;
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[1].classname(), Some("light"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_entities_quake_charset() -> Result<()> {
+        let mut lump = b"{\n\"classname\" \"worldspawn\"\n\"message\" \"".to_vec();
+        lump.extend([b'D' | 128, b'M' | 128, b'3', b' ', b'(', b'x' | 128, b')']);
+
+        lump.extend([b' ', 0x90, 0x92, 0x13, 0x91, b' ', 0x85, b' ', b'"' | 128]);
+        lump.extend(b"\"\n}\n");
+
+        assert!(parse_entities(&lump, false).is_err());
+        assert_eq!(
+            parse_entities(&lump, true)?[0].get("message"),
+            Some(&"DM3 (x) [01] • \u{201d}".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_faces_grouped_by_texture() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let groups = bsp.faces_grouped_by_texture();
+
+        assert!(groups.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(
+            groups.iter().map(|(_, faces)| faces.len()).sum::<usize>(),
+            bsp.faces.len()
+        );
+        for (texture_index, faces) in groups {
+            for face in faces {
+                let info = &bsp.texture_info[face.texture_info_index];
+                assert_eq!(info.texture_index, texture_index);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_texture_mip_out_of_bounds() -> Result<()> {
+        let texture_lump = |mip_offsets: [u32; 4]| {
+            let mut lump = Vec::new();
+            lump.extend(1i32.to_le_bytes());
+            lump.extend(8i32.to_le_bytes());
+            lump.extend(b"bad_mip\0\0\0\0\0\0\0\0\0");
+            lump.extend(16u32.to_le_bytes());
+            lump.extend(16u32.to_le_bytes());
+            for offset in mip_offsets {
+                lump.extend(offset.to_le_bytes());
+            }
+            lump.extend([0; 256 + 64 + 16 + 4]);
+            Cursor::new(lump)
+        };
+
+        assert_eq!(
+            Texture::parse(&mut texture_lump([40, 296, 360, 376]))?.len(),
+            1
+        );
+
+        let err = Texture::parse(&mut texture_lump([40, 10_000, 360, 376])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Mip level 1 of texture bad_mip is out of bounds"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_level_transitions() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.level_transitions(), vec!["dm4".to_string()]);
+
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        assert!(bsp.level_transitions().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_texture_names() -> Result<()> {
+        let file = &mut fs::File::open("tests/files/dm3_gpl.bsp")?;
+        file.seek(SeekFrom::Start(4 + 2 * 8))?;
+        let e_wall_textures = Entry::read(file, None)?;
+
+        let names = texture_names(file, e_wall_textures.offset as u64)?;
+        let bsp = BspFile::parse(file)?;
+        assert_eq!(names.len(), bsp.textures.len());
+        assert!(names.iter().zip(&bsp.textures).all(|(n, t)| n == &t.name));
+        assert!(names.contains(&"sky4".to_string()));
+
+        let corrupt = i32::MAX.to_le_bytes();
+        assert!(texture_names(&mut Cursor::new(corrupt), 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_edge_references() -> Result<()> {
+        let mut bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        assert!(bsp.verify_edge_references().is_ok());
+
+        bsp.edge_list[3] = -(bsp.edges.len() as i32);
+        assert_eq!(
+            bsp.verify_edge_references().unwrap_err().to_string(),
+            "Edge list entry 3 references edge -760, but there are only 760 edges"
+        );
+
+        let vertices = [Vector3::new(0.0, 0.0, 0.0)];
+        let edge_lump = [0, 0, 1, 0];
+        assert!(Edge::parse(1, &vertices, &mut Cursor::new(edge_lump)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spawn_centroid() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let centroid = bsp.spawn_centroid().unwrap();
+        let (min, max) = bsp.models[0].bound;
+
+        assert!((0..3).all(|i| min[i] <= centroid[i] && centroid[i] <= max[i]));
+        assert_eq!(BspFile::default().spawn_centroid(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_entity_world_bounds() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let door = &bsp.entities[2];
+        assert_eq!(door.classname(), Some("func_door"));
+        assert_eq!(
+            bsp.entity_world_bounds(door),
+            Some(BoundingBox::new(
+                bsp.models[1].bound.0,
+                bsp.models[1].bound.1
+            ))
+        );
+
+        let light = &bsp.entities[8];
+        assert_eq!(light.classname(), Some("light"));
+        assert_eq!(
+            bsp.entity_world_bounds(light),
+            Some(BoundingBox::new(
+                Vector3::new(-72.0, -648.0, -216.0),
+                Vector3::new(-56.0, -632.0, -200.0)
+            ))
+        );
+
+        assert_eq!(bsp.entity_world_bounds(&HashMap::new()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compiler_hint() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.compiler_hint(), None);
+
+        let worldspawn = |key, value| worldspawn_bsp(&[(key, value)]);
+        assert_eq!(
+            worldspawn("_generator", "ericw-tools 0.18.1").compiler_hint(),
+            Some("ericw-tools 0.18.1".to_string())
+        );
+        assert_eq!(
+            worldspawn("_tb_mod", "ad").compiler_hint(),
+            Some("TrenchBroom (ad)".to_string())
+        );
+        assert_eq!(
+            worldspawn("_tb_textures", "textures/base").compiler_hint(),
+            Some("TrenchBroom".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_texture_downsampled() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let sky = bsp.textures.iter().find(|t| t.name == "sky4").unwrap();
+
+        assert_eq!(sky.downsampled(256), &sky.pictures[0]);
+        assert_eq!(sky.downsampled(100), &sky.pictures[2]);
+        assert_eq!(sky.downsampled(100).width, 64);
+        assert_eq!(sky.downsampled(1), &sky.pictures[3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_complexity_metrics() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let metrics = bsp.complexity_metrics();
+
+        assert!(metrics.surface_area > 0.0);
+        assert!(metrics.volume > 0.0);
+        assert_eq!(
+            metrics.surface_to_volume,
+            metrics.surface_area / metrics.volume
+        );
+        assert_eq!(
+            BspFile::default().complexity_metrics(),
+            ComplexityMetrics::default()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sun_light() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.sun_light(), None);
+
+        let bsp = worldspawn_bsp(&[
+            ("_sunlight", "200"),
+            ("_sun_mangle", "90 -45 0"),
+            ("_sunlight_color", "255 127.5 0"),
+        ]);
+        let sun = bsp.sun_light().unwrap();
+        assert_eq!(sun.intensity, 200.0);
+        assert!(sun.direction.x.abs() < 1e-6);
+        assert!((sun.direction.y - 0.5f32.sqrt()).abs() < 1e-6);
+        assert!((sun.direction.z + 0.5f32.sqrt()).abs() < 1e-6);
+        assert_eq!(sun.color, Vector3::new(1.0, 0.5, 0.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_adjacent_faces() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let floor_index = (0..bsp.faces.len())
+            .find(|&i| face_normal(&bsp, &bsp.faces[i]).z == 1.0)
+            .unwrap();
+        let neighbours = bsp.adjacent_faces(floor_index);
+
+        assert!(!neighbours.is_empty());
+        assert!(!neighbours.contains(&floor_index));
+        for i in neighbours {
+            assert!(bsp.adjacent_faces(i).contains(&floor_index));
+        }
+        assert!(bsp.adjacent_faces(bsp.faces.len()).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_texture_indices() -> Result<()> {
+        let mut bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        assert!(bsp.verify_texture_indices().is_ok());
+
+        bsp.texture_info[5].texture_index = 8;
+        assert_eq!(
+            bsp.verify_texture_indices().unwrap_err().to_string(),
+            "Texture info 5 references texture 8, but there are only 8 textures"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parse_dir() {
+        let results = parse_dir("tests/files");
+        let paths: Vec<&Path> = results.iter().map(|(p, _)| p.as_path()).collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("tests/files/dm3_gpl.bsp"),
+                Path::new("tests/files/povdmm4.bsp"),
+            ]
+        );
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
+        assert!(parse_dir("tests/missing").is_empty());
+    }
+
+    #[test]
+    fn test_spawns_by_type() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let spawns = bsp.spawns_by_type();
+        assert_eq!(spawns.len(), 2);
+        assert_eq!(spawns["info_player_start"].len(), 1);
+        assert_eq!(spawns["info_player_deathmatch"].len(), 6);
+
+        let spawn = |classname: &str, origin: &str| {
+            HashMap::from([
+                ("classname".to_string(), classname.to_string()),
+                ("origin".to_string(), origin.to_string()),
+            ])
+        };
+        let bsp = BspFile {
+            entities: vec![
+                spawn("info_player_deathmatch", "0 0 24"),
+                spawn("info_player_coop", "64 0 24"),
+                spawn("info_player_coop", "128 0 24"),
+            ],
+            ..BspFile::default()
+        };
+        assert_eq!(
+            bsp.spawns_by_type(),
+            HashMap::from([
+                (
+                    "info_player_deathmatch".to_string(),
+                    vec![Vector3::new(0.0, 0.0, 24.0)]
+                ),
+                (
+                    "info_player_coop".to_string(),
+                    vec![
+                        Vector3::new(64.0, 0.0, 24.0),
+                        Vector3::new(128.0, 0.0, 24.0)
+                    ]
+                ),
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_hull_headnode() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.models[0].hull_headnode(0), Some(0));
+        assert_eq!(bsp.models[0].hull_headnode(1), Some(0));
+        assert_eq!(bsp.models[0].hull_headnode(4), None);
+        assert!(bsp.models[1].hull_headnode(0) > Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_texture_content() {
+        let content = |name: &str| {
+            Texture {
+                name: name.to_string(),
+                ..Texture::default()
+            }
+            .texture_content()
+        };
+        assert_eq!(content("*lava1"), ContentType::Lava);
+        assert_eq!(content("*slime0"), ContentType::Slime);
+        assert_eq!(content("*water1"), ContentType::Water);
+        assert_eq!(content("*teleport"), ContentType::Water);
+        assert_eq!(content("sky1"), ContentType::Sky);
+        assert_eq!(content("clip"), ContentType::Clip);
+        assert_eq!(content("trigger"), ContentType::Trigger);
+        assert_eq!(content("metal4_4"), ContentType::Solid);
+    }
+
+    #[test]
+    fn test_entities_pretty() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let text = bsp.entities_pretty();
+
+        assert!(text.starts_with(
+            r#"{
+    "classname" "worldspawn"
+    "message" "DMM4 Arena\nBy Povo-Hat (http://povo-hat.besmella-quake.com)\n"
+    "sounds" "0"
+    "worldtype" "1"
+}
+{
+    "classname" "info_player_start"
+    "origin" "-128 -256 -192"
+}
+"#
+        ));
+        assert_eq!(parse_entities(text.as_bytes(), false)?, bsp.entities);
+        Ok(())
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_parse_bytes_buf() -> Result<()> {
+        let data = fs::read("tests/files/povdmm4.bsp")?;
+        let bsp = parse_bytes_buf(bytes::Bytes::from(data.clone()))?;
+        assert_eq!(bsp, BspFile::parse(&mut Cursor::new(data))?);
+        Ok(())
+    }
+
+    /// Bsp with faces given as loops of vertex ids, sharing edges between faces.
+    fn vertex_loops_bsp(vertices: &[[f32; 3]], faces: &[Vec<usize>]) -> BspFile {
+        let mut bsp = BspFile {
+            vertices: vertices.iter().map(|&v| v.into()).collect(),
+            edges: vec![Edge(
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 0.0),
+            )],
+            ..BspFile::default()
+        };
+        let mut edge_ids: Vec<(usize, usize)> = vec![(0, 0)];
+
+        for ids in faces {
+            let start = bsp.edge_list.len();
+            for (i, &a) in ids.iter().enumerate() {
+                let b = ids[(i + 1) % ids.len()];
+                let index = match edge_ids.iter().position(|&e| e == (b, a)) {
+                    Some(reverse) => -(reverse as i32),
+                    None => {
+                        edge_ids.push((a, b));
+                        bsp.edges.push(Edge(bsp.vertices[a], bsp.vertices[b]));
+                        edge_ids.len() as i32 - 1
+                    }
+                };
+                bsp.edge_list.push(index);
+            }
+            bsp.faces.push(Face {
+                edge_indexes: start..bsp.edge_list.len(),
+                ..Face::default()
+            });
+        }
+        bsp
+    }
+
+    fn cube_bsp() -> BspFile {
+        let vertices = [
+            [0.0, 0.0, 0.0],
+            [64.0, 0.0, 0.0],
+            [64.0, 64.0, 0.0],
+            [0.0, 64.0, 0.0],
+            [0.0, 0.0, 64.0],
+            [64.0, 0.0, 64.0],
+            [64.0, 64.0, 64.0],
+            [0.0, 64.0, 64.0],
+        ];
+        let faces = [
+            vec![0, 1, 2, 3],
+            vec![4, 7, 6, 5],
+            vec![0, 4, 5, 1],
+            vec![1, 5, 6, 2],
+            vec![2, 6, 7, 3],
+            vec![3, 7, 4, 0],
+        ];
+        vertex_loops_bsp(&vertices, &faces)
+    }
+
+    #[test]
+    fn test_geometry_islands() -> Result<()> {
+        let bsp = cube_bsp();
+        assert_eq!(bsp.edges.len(), 13);
+        assert_eq!(bsp.geometry_islands(), vec![vec![0, 1, 2, 3, 4, 5]]);
+
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let islands = bsp.geometry_islands();
+        assert!(islands.len() > 1);
+        assert_eq!(islands.iter().map(Vec::len).sum::<usize>(), bsp.faces.len());
+        Ok(())
+    }
+
+    fn entity(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// Map with only a worldspawn entity holding `pairs`.
+    fn worldspawn_bsp(pairs: &[(&str, &str)]) -> BspFile {
+        let mut worldspawn = entity(pairs);
+        worldspawn.insert("classname".to_string(), "worldspawn".to_string());
+        BspFile {
+            entities: vec![worldspawn],
+            ..BspFile::default()
+        }
+    }
+
+    fn named_textures(names: &[&str]) -> BspFile {
+        BspFile {
+            textures: names
+                .iter()
+                .map(|name| Texture {
+                    name: name.to_string(),
+                    ..Texture::default()
+                })
+                .collect(),
+            ..BspFile::default()
+        }
+    }
+
+    #[test]
+    fn test_animation_chains() {
+        let bsp = named_textures(&[
+            "+0button", "+abutton", "+1button", "wall", "+Bbutton", "+0slip", "+1slip",
+        ]);
+        assert_eq!(bsp.animation_chains(), vec![vec![0, 2], vec![5, 6]]);
+        assert_eq!(bsp.animation_alt_chains(), vec![vec![1, 4]]);
+
+        let bsp = named_textures(&["+2slip", "+0slip", "+Bslip", "+1slip", "+aslip"]);
+        assert_eq!(bsp.animation_chains(), vec![vec![1, 3, 0]]);
+        assert_eq!(bsp.animation_alt_chains(), vec![vec![4, 2]]);
+    }
+
+    #[test]
+    fn test_lightmap_allocation() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let rects = bsp.lightmap_allocation();
+
+        assert_eq!(
+            rects.len(),
+            bsp.faces.iter().filter(|f| f.has_lightmap()).count()
+        );
+        // earlier blocks are reused by smaller lightmaps once a new block is started
+        assert!(rects.windows(2).any(|w| w[1].block < w[0].block));
+        for (i, a) in rects.iter().enumerate() {
+            assert!(a.x + a.width < 128 && a.y + a.height <= 128);
+
+            for b in &rects[i + 1..] {
+                let overlaps = a.block == b.block
+                    && a.x < b.x + b.width
+                    && b.x < a.x + a.width
+                    && a.y < b.y + b.height
+                    && b.y < a.y + a.height;
+                assert!(!overlaps, "{a:?} overlaps {b:?}");
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_map_source() -> Result<()> {
+        let entities = parse_map_source(&mut fs::File::open("tests/files/box.map")?)?;
+
+        assert_eq!(entities.len(), 3);
+        assert_eq!(
+            entities[0],
+            HashMap::from([
+                ("classname".to_string(), "worldspawn".to_string()),
+                ("wad".to_string(), "gfx/base.wad".to_string()),
+                ("message".to_string(), "Box".to_string()),
+            ])
+        );
+        assert_eq!(entities[2].get("model"), None);
+        assert_eq!(entities[2].classname(), Some("func_door"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_unused_textures() -> Result<()> {
+        let mut bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let face_textures = |bsp: &BspFile| -> Vec<String> {
+            bsp.faces
+                .iter()
+                .map(|f| {
+                    let info = &bsp.texture_info[f.texture_info_index];
+                    bsp.textures[info.texture_index].name.clone()
+                })
+                .collect()
+        };
+        let expected = face_textures(&bsp);
+        let texture_count = bsp.textures.len();
+
+        bsp.textures.insert(
+            0,
+            Texture {
+                name: "unused".to_string(),
+                ..Texture::default()
+            },
+        );
+        bsp.texture_info
+            .iter_mut()
+            .for_each(|i| i.texture_index += 1);
+
+        assert_eq!(bsp.prune_unused_textures(), 1);
+        assert_eq!(bsp.textures.len(), texture_count);
+        assert_eq!(face_textures(&bsp), expected);
+        assert_eq!(bsp.prune_unused_textures(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_unused_textures_keeps_animations() {
+        let mut bsp = named_textures(&["+1x", "unused", "+ax", "+0x", "+0y", "wall"]);
+        bsp.texture_info = [3, 5]
+            .into_iter()
+            .map(|texture_index| TextureInfo {
+                vector_s: Vector3::unit_x(),
+                dist_s: 0.0,
+                vector_t: Vector3::unit_y(),
+                dist_t: 0.0,
+                texture_index,
+                animated: true,
+            })
+            .collect();
+
+        assert_eq!(bsp.prune_unused_textures(), 2);
+        let names: Vec<_> = bsp.textures.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["+1x", "+ax", "+0x", "wall"]);
+        assert_eq!(bsp.texture_info[0].texture_index, 2);
+        assert_eq!(bsp.texture_info[1].texture_index, 3);
+    }
+
+    #[test]
+    fn test_brush_faces() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let teleport = bsp
+            .entities
+            .iter()
+            .find(|e| e.classname() == Some("trigger_teleport"))
+            .unwrap();
+
+        let faces = bsp.brush_faces(teleport).unwrap();
+        let model = bsp.entity_model(teleport).unwrap();
+        assert!(!faces.is_empty());
+        assert_eq!(faces.len(), model.face_indexes.len());
+        assert_eq!(faces[0], &bsp.faces[model.face_indexes.start]);
+
+        assert_eq!(bsp.brush_faces(bsp.worldspawn().unwrap()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_switchable_lights() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.switchable_lights(), vec![]);
+
+        let light = |origin: &str, targetname: Option<&str>, style: Option<&str>| {
+            let mut entity = HashMap::from([
+                ("classname".to_string(), "light".to_string()),
+                ("origin".to_string(), origin.to_string()),
+            ]);
+            if let Some(targetname) = targetname {
+                entity.insert("targetname".to_string(), targetname.to_string());
+            }
+            if let Some(style) = style {
+                entity.insert("style".to_string(), style.to_string());
+            }
+            entity
+        };
+        let bsp = BspFile {
+            entities: vec![
+                light("0 0 0", None, None),
+                light("64 0 32", Some("lamp"), Some("32")),
+                light("0 64 32", Some("other"), None),
+            ],
+            ..BspFile::default()
+        };
+        assert_eq!(
+            bsp.switchable_lights(),
+            vec![
+                SwitchableLight {
+                    origin: Vector3::new(64.0, 0.0, 32.0),
+                    targetname: "lamp".to_string(),
+                    style: 32,
+                },
+                SwitchableLight {
+                    origin: Vector3::new(0.0, 64.0, 32.0),
+                    targetname: "other".to_string(),
+                    style: 0,
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_lenient() -> Result<()> {
+        // move the models lump to the end and cut it off within the fourth model
+        let mut data = fs::read("tests/files/dm3_gpl.bsp")?;
+        let (models_offset, models_size) = (340216, 448);
+        let models = data[models_offset..models_offset + models_size].to_vec();
+        let end_offset = data.len() as i32;
+        data[116..120].copy_from_slice(&end_offset.to_le_bytes());
+        data.extend_from_slice(&models[..SIZE_MODEL * 3 + SIZE_MODEL / 2]);
+
+        assert!(BspFile::parse(&mut Cursor::new(&data)).is_err());
+
+        let options = ParseOptions {
+            lenient: true,
+            ..ParseOptions::default()
+        };
+        let bsp = BspFile::parse_with_options(&mut Cursor::new(&data), &options)?;
+        let expected = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.models.len(), 3);
+        assert_eq!(bsp.models[..], expected.models[..3]);
+        assert_eq!(bsp.entities, expected.entities);
+        assert_eq!(bsp.faces, expected.faces);
+
+        let bsp =
+            BspFile::parse_with_options(&mut fs::File::open("tests/files/dm3_gpl.bsp")?, &options)?;
+        assert_eq!(bsp, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_submodel_owner() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+
+        let owner = &bsp.entities[bsp.submodel_owner(1).unwrap()];
+        assert_eq!(owner.classname(), Some("func_plat"));
+        assert_eq!(owner.value("model"), Some("*1"));
+
+        let owner = &bsp.entities[bsp.submodel_owner(6).unwrap()];
+        assert_eq!(owner.classname(), Some("trigger_changelevel"));
+
+        assert_eq!(bsp.submodel_owner(0), None);
+        assert_eq!(bsp.submodel_owner(7), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dominant_texture() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let (name, area) = bsp.dominant_texture().unwrap();
+        assert_eq!(name, "sfloor4_2");
+        assert!(area > 0.0 && area < bsp.complexity_metrics().surface_area);
+        assert_eq!(BspFile::default().dominant_texture(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_bounds() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let bounds = bsp.model_bounds();
+
+        assert_eq!(bounds.len(), bsp.models.len());
+        assert_eq!(Some(bounds[0]), bsp.world_bounds());
+        assert_eq!(
+            bounds[1],
+            BoundingBox::new(bsp.models[1].bound.0, bsp.models[1].bound.1)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_entities_borrowed() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let entities = bsp.entities_borrowed();
+
+        assert_eq!(entities.len(), bsp.entities.len());
+        assert_eq!(entities[0].get("classname"), Some(&"worldspawn"));
+        assert_eq!(entities[0].len(), bsp.entities[0].len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_faces_without_texture() -> Result<()> {
+        let mut bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.faces_without_texture(), Vec::<usize>::new());
+
+        bsp.faces[3].texture_info_index = bsp.texture_info.len();
+        assert_eq!(bsp.faces_without_texture(), vec![3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_geometry_fingerprint() -> Result<()> {
+        let dm3 = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let povdmm4 = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let fingerprint = dm3.geometry_fingerprint();
+
+        assert_eq!(fingerprint, dm3.geometry_fingerprint());
+        assert_ne!(fingerprint, povdmm4.geometry_fingerprint());
+
+        let dm3 = dm3.with_entities(povdmm4.entities.clone());
+        assert_eq!(dm3.geometry_fingerprint(), fingerprint);
+        Ok(())
+    }
+
+    #[test]
+    fn test_worldtype() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.worldtype(), Some(WorldType::Base));
+        assert_eq!(bsp.cd_track(), Some(6));
+
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        assert_eq!(bsp.worldtype(), Some(WorldType::Runic));
+        assert_eq!(bsp.cd_track(), Some(0));
+
+        assert_eq!(BspFile::default().worldtype(), None);
+        assert_eq!(BspFile::default().cd_track(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_waypoint_graph() {
+        let waypoint = |number: &str, origin: &str, links: &[&str]| {
+            let mut entity = HashMap::from([
+                ("classname".to_string(), "waypoint".to_string()),
+                ("waypointnumber".to_string(), number.to_string()),
+                ("origin".to_string(), origin.to_string()),
+            ]);
+            for (i, link) in links.iter().enumerate() {
+                entity.insert(format!("wp{i}"), link.to_string());
+            }
+            entity
+        };
+        let bsp = BspFile {
+            entities: vec![
+                waypoint("1", "0 0 0", &["2", "3"]),
+                waypoint("2", "64 0 0", &["1"]),
+                waypoint("3", "0 64 0", &["1", "9"]),
+            ],
+            ..BspFile::default()
+        };
+
+        let graph = bsp.waypoint_graph();
+        assert_eq!(graph.nodes.len(), 3);
+        assert_eq!(graph.nodes[&2], Vector3::new(64.0, 0.0, 0.0));
+        assert_eq!(graph.edges, vec![(1, 2), (1, 3), (2, 1), (3, 1)]);
+        assert_eq!(
+            BspFile::default().waypoint_graph(),
+            WaypointGraph::default()
+        );
+    }
+
+    #[test]
+    fn test_dangling_targets() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.dangling_targets(), vec![]);
+
+        let bsp = BspFile {
+            entities: vec![
+                entity(&[("classname", "worldspawn")]),
+                entity(&[("classname", "func_button"), ("target", "door1")]),
+                entity(&[("classname", "func_door"), ("targetname", "door1")]),
+                entity(&[("classname", "func_button"), ("target", "door2")]),
+            ],
+            ..BspFile::default()
+        };
+        assert_eq!(bsp.dangling_targets(), vec![(3, "door2".to_string())]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_entities() -> Result<()> {
+        let dm3 = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let povdmm4 = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let (dm3_count, povdmm4_count) = (dm3.entities.len(), povdmm4.entities.len());
+
+        let entities = merge_entities(&[dm3, povdmm4]);
+        assert_eq!(entities.len(), dm3_count + povdmm4_count);
+        assert_eq!(entities[0].value("_source_index"), Some("0"));
+        assert_eq!(entities[dm3_count].value("_source_index"), Some("1"));
+        assert_eq!(entities[dm3_count].classname(), Some("worldspawn"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_directed_edges() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let edges: Vec<_> = bsp.directed_edges().collect();
+        assert_eq!(edges.len(), bsp.edge_list.len());
+
+        for (&index, (start, end)) in bsp.edge_list.iter().zip(edges) {
+            let edge = &bsp.edges[index.unsigned_abs() as usize];
+            match index >= 0 {
+                true => assert_eq!((start, end), (edge.0, edge.1)),
+                false => assert_eq!((start, end), (edge.1, edge.0)),
+            }
+        }
+
+        // consecutive edges of a face are connected
+        let face = &bsp.faces[0];
+        let face_edges: Vec<_> = bsp.edge_list[face.edge_indexes.clone()]
+            .iter()
+            .filter_map(|&i| bsp.directed_edge(i))
+            .collect();
+        for pair in face_edges.windows(2) {
+            assert_eq!(pair[0].1, pair[1].0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_faces_by_distance() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let from = Vector3::new(-64.0, -640.0, -208.0);
+        let distances = bsp.faces_by_distance(from);
+
+        assert_eq!(distances.len(), bsp.faces.len());
+        assert!(distances.windows(2).all(|w| w[0].1 <= w[1].1));
+
+        let (nearest, distance) = distances[0];
+        let center = face_center(&bsp, &bsp.faces[nearest]).unwrap();
+        assert_eq!((center - from).magnitude(), distance);
+        for face in &bsp.faces {
+            let center = face_center(&bsp, face).unwrap();
+            assert!((center - from).magnitude() >= distance);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_textures_by_size() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        assert_eq!(
+            bsp.textures_by_size(),
+            BTreeMap::from([
+                ((32, 32), vec![7]),
+                ((64, 64), vec![0, 1, 2, 3, 4, 6]),
+                ((256, 128), vec![5]),
+            ])
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_lazy_textures() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let textures = LazyTextures::open("tests/files/dm3_gpl.bsp")?;
+        assert_eq!(textures.len(), bsp.textures.len());
+        assert!(!textures.is_decoded(2));
+
+        assert_eq!(textures.get(2)?, &bsp.textures[2]);
+        assert!(textures.is_decoded(2));
+        assert!((0..textures.len())
+            .filter(|&i| i != 2)
+            .all(|i| !textures.is_decoded(i)));
+
+        assert!(textures.get(textures.len()).is_err());
+
+        let mut data = fs::read("tests/files/dm3_gpl.bsp")?;
+        let texture_offset = Entry::read(&mut Cursor::new(&data[20..28]), None)?.offset as usize;
+        data[texture_offset..texture_offset + 4].copy_from_slice(&i32::MAX.to_le_bytes());
+        let path = std::env::temp_dir().join("bspparser_lazy_textures_corrupt.bsp");
+        fs::write(&path, &data)?;
+        assert!(LazyTextures::open(&path).is_err());
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_entities_to_json() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let json: Vec<HashMap<String, String>> = serde_json::from_str(&bsp.entities_to_json())?;
+        assert_eq!(json.len(), bsp.entities.len());
+        assert_eq!(json, bsp.entities);
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_light() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.min_light(), 0.0);
+
+        let bsp = worldspawn_bsp(&[("_minlight", "10")]);
+        assert_eq!(bsp.min_light(), 10.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_raycast_faces() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let origin = Vector3::new(-64.0, -640.0, -208.0);
+
+        let (index, distance) = bsp.raycast_faces(origin, -Vector3::unit_z()).unwrap();
+        let face = &bsp.faces[index];
+        let plane = &bsp.planes[face.plane_index];
+        let hit = origin - Vector3::unit_z() * distance;
+        assert!(face_normal(&bsp, face).z > 0.99);
+        assert!((plane.normal.dot(hit) - plane.distance).abs() < 0.01);
+
+        let (_, up_distance) = bsp
+            .raycast_faces(origin, Vector3::new(0.0, 0.0, 2.0))
+            .unwrap();
+        assert!(up_distance > 0.0);
+        assert_eq!(bsp.raycast_faces(origin, Vector3::new(0.0, 0.0, 0.0)), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plane_face_fanout() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let fanout = bsp.plane_face_fanout();
+
+        assert_eq!(fanout.values().sum::<usize>(), bsp.faces.len());
+        assert!(fanout.keys().all(|&i| i < bsp.planes.len()));
+        assert!(fanout.values().max().unwrap() > &1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_geometry_only() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let geometry =
+            BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?.geometry_only();
+
+        assert!(geometry.light_maps.is_empty());
+        assert!(geometry
+            .textures
+            .iter()
+            .all(|t| t.pictures[0].data.is_empty()));
+        assert_eq!(geometry.textures.len(), bsp.textures.len());
+        assert_eq!(geometry.faces, bsp.faces);
+        assert_eq!(geometry.edges, bsp.edges);
+        assert_eq!(geometry.models, bsp.models);
+        assert_eq!(geometry.geometry_fingerprint(), bsp.geometry_fingerprint());
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_vis_data() -> Result<()> {
+        let mut data = fs::read("tests/files/dm3_gpl.bsp")?;
+        assert!(BspFile::parse(&mut Cursor::new(&data))?.has_vis_data());
+
+        data[40..44].copy_from_slice(&0i32.to_le_bytes());
+        assert!(!BspFile::parse(&mut Cursor::new(&data))?.has_vis_data());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sound_references() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.sound_references(), vec!["ambience/comp1.wav"]);
+
+        let bsp = BspFile {
+            entities: vec![
+                entity(&[("classname", "worldspawn"), ("sounds", "4")]),
+                entity(&[("classname", "ambient_drip")]),
+                entity(&[
+                    ("classname", "func_door"),
+                    ("noise1", "doors/creak.wav"),
+                    ("noise2", "doors/close.wav"),
+                ]),
+                entity(&[("classname", "info_notnull"), ("noise", "doors/creak.wav")]),
+                entity(&[("classname", "ambient_drip")]),
+            ],
+            ..BspFile::default()
+        };
+        assert_eq!(
+            bsp.sound_references(),
+            vec!["ambience/drip1.wav", "doors/creak.wav", "doors/close.wav"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lump_integrity_report() -> Result<()> {
+        let mut data = fs::read("tests/files/dm3_gpl.bsp")?;
+        let header = BspHeader::read(&mut Cursor::new(&data))?;
+        let bsp = BspFile::parse(&mut Cursor::new(&data))?;
+        assert_eq!(BspVersion::try_from(header.version)?, BspVersion::Quake);
+        assert_eq!(bsp.lump_integrity_report(&header), vec![]);
+
+        // move the leaves lump 8 bytes back, into the planes lump
+        let leaves_offset = header.lumps[10].offset - 8;
+        data[4 + 10 * 8..4 + 10 * 8 + 4].copy_from_slice(&leaves_offset.to_le_bytes());
+        let header = BspHeader::read(&mut Cursor::new(&data))?;
+        let bsp = BspFile::parse(&mut Cursor::new(&data))?;
+        assert!(bsp
+            .lump_integrity_report(&header)
+            .contains(&LumpIssue::Overlap {
+                first: 1,
+                second: 10
+            }));
+
+        let bsp = BspFile {
+            models: vec![],
+            ..bsp
+        };
+        assert!(bsp
+            .lump_integrity_report(&header)
+            .contains(&LumpIssue::CountMismatch {
+                lump: 14,
+                expected: 7,
+                actual: 0
+            }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_nodes() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.nodes.len(), 1454);
+
+        let root = &bsp.nodes[bsp.models[0].node_indexes[0] as usize];
+        let world = bsp.world_bounds().unwrap();
+        assert!(root
+            .bounds
+            .contains(world.min + Vector3::new(1.0, 1.0, 1.0)));
+        assert!(bsp.nodes.iter().all(|n| n.plane_index < bsp.planes.len()));
+        assert!(bsp
+            .nodes
+            .iter()
+            .all(|n| n.face_indexes.end <= bsp.faces.len()));
+
+        // negative children reference leaves
+        assert!(bsp.nodes.iter().any(|n| n.children.iter().any(|&c| c < 0)));
+        assert!(bsp
+            .nodes
+            .iter()
+            .flat_map(|n| n.children)
+            .all(|c| c < bsp.nodes.len() as i32));
+        Ok(())
+    }
+
+    #[test]
+    fn test_leaves() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.leaves.len(), 679);
+
+        // leaf 0 is the shared solid leaf outside the map
+        assert_eq!(bsp.leaves[0].contents, LeafContents::Solid);
+        assert!(bsp.leaves.iter().any(|l| l.contents == LeafContents::Empty));
+        assert!(bsp.leaves.iter().any(|l| l.contents == LeafContents::Sky));
+        assert!(!bsp
+            .leaves
+            .iter()
+            .any(|l| matches!(l.contents, LeafContents::Unknown(_))));
+
+        assert_eq!(bsp.face_list.len(), 4205);
+        assert!(bsp
+            .leaves
+            .iter()
+            .all(|l| l.face_list_indexes.end <= bsp.face_list.len()));
+        assert!(bsp
+            .face_list
+            .iter()
+            .all(|&i| (i as usize) < bsp.faces.len()));
+
+        assert_eq!(LeafContents::from(-3), LeafContents::Water);
+        assert_eq!(LeafContents::from(-7), LeafContents::Unknown(-7));
+        Ok(())
+    }
+
+    /// Version 30 map with no lumps but the textures: an embedded 16x16 texture using palette
+    /// color 1 and an external texture.
+    fn half_life_bsp() -> Vec<u8> {
+        let header_size: i32 = 4 + 15 * 8;
+        let miptex = |name: &[u8], offsets: [u32; 4]| -> Vec<u8> {
+            let mut data = name.to_vec();
+            data.resize(16, 0);
+            for value in [16, 16].into_iter().chain(offsets) {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data
+        };
+
+        let mut texture_lump = Vec::new();
+        for value in [2i32, 12, 12 + 40 + 340 + 2 + 768] {
+            texture_lump.extend_from_slice(&value.to_le_bytes());
+        }
+        texture_lump.extend(miptex(b"embedded", [40, 40 + 256, 40 + 320, 40 + 336]));
+        texture_lump.extend_from_slice(&[1; 340]);
+        texture_lump.extend_from_slice(&256u16.to_le_bytes());
+        texture_lump.extend((0..256).flat_map(|i| [i as u8, 0, 255]));
+        texture_lump.extend(miptex(b"external", [0; 4]));
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&30i32.to_le_bytes());
+        for lump in 0..15 {
+            let size = match lump {
+                2 => texture_lump.len() as i32,
+                _ => 0,
+            };
+            data.extend_from_slice(&header_size.to_le_bytes());
+            data.extend_from_slice(&size.to_le_bytes());
+        }
+        data.extend_from_slice(&texture_lump);
+        data
+    }
+
+    #[test]
+    fn test_half_life_lightmap() -> Result<()> {
+        use crate::helpers::{face_brightness, face_lightmap};
+
+        // a 32x16 face covering 3x2 luxels
+        let mut bsp = BspFile::parse(&mut Cursor::new(half_life_bsp()))?;
+        let (a, b) = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(32.0, 0.0, 0.0));
+        let (c, d) = (Vector3::new(32.0, 16.0, 0.0), Vector3::new(0.0, 16.0, 0.0));
+        bsp.edges = vec![Edge(a, a), Edge(a, b), Edge(b, c), Edge(c, d), Edge(d, a)];
+        bsp.edge_list = vec![1, 2, 3, 4];
+        bsp.texture_info.push(TextureInfo {
+            vector_s: Vector3::unit_x(),
+            dist_s: 0.0,
+            vector_t: Vector3::unit_y(),
+            dist_t: 0.0,
+            texture_index: 0,
+            animated: false,
+        });
+        bsp.faces.push(Face {
+            edge_indexes: 0..4,
+            light_map: 3,
+            ..Face::default()
+        });
+        bsp.light_maps = [[0; 3]]
+            .into_iter()
+            .chain([[0, 51, 255]; 6])
+            .flatten()
+            .collect();
+
+        let face = &bsp.faces[0];
+        assert_eq!(
+            face_lightmap(&bsp, face),
+            Some([0, 51, 255].repeat(6).as_slice())
+        );
+        assert_eq!(face_brightness(&bsp, face), Some(102.0 / 255.0));
+
+        #[cfg(feature = "image")]
+        {
+            let atlas = crate::helpers::export_lightmap_atlas(&bsp)?;
+            assert_eq!(atlas.get_pixel(2, 1).0, [0, 51, 255]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_hl_texture_palette() -> Result<()> {
+        let mut data = half_life_bsp();
+        let bsp = BspFile::parse(&mut Cursor::new(&data))?;
+        let texture = &bsp.textures[0];
+
+        // header, texture lump offsets and the miptex header and mips of the first texture
+        let palette_offset = 124 + 12 + 40 + 340;
+        let mut r = Cursor::new(&data);
+        r.seek(SeekFrom::Start(palette_offset))?;
+        let palette = hl_texture_palette(&mut r, texture)?;
+        assert_eq!(palette[3..6], [1, 0, 255]);
+        assert_eq!(texture.palette.as_deref(), Some(palette.as_slice()));
+        assert_eq!(texture.to_rgb(0), Some([1, 0, 255].repeat(256)));
+
+        data[palette_offset as usize] = 255;
+        let mut r = Cursor::new(&data);
+        r.seek(SeekFrom::Start(palette_offset))?;
+        assert!(hl_texture_palette(&mut r, texture).is_err());
+        assert!(BspFile::parse(&mut Cursor::new(&data)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_half_life() -> Result<()> {
+        let mut data = half_life_bsp();
+        let bsp = BspFile::parse(&mut Cursor::new(&data))?;
+        assert_eq!(bsp.version, BspVersion::HalfLife);
+        assert_eq!(bsp.version.version_magic(), [30, 0, 0, 0]);
+        assert_eq!(bsp.version.version_magic(), data[0..4]);
+        assert_eq!(BspVersion::Quake.version_magic(), [29, 0, 0, 0]);
+        assert_eq!(bsp.textures.len(), 2);
+
+        let texture = &bsp.textures[0];
+        assert_eq!(texture.name, "embedded");
+        assert_eq!(texture.pictures[3].data, vec![1; 4]);
+        assert_eq!(texture.palette.as_ref().map(|p| p.len()), Some(768));
+        assert_eq!(texture.to_rgb(3), Some([1, 0, 255].repeat(4)));
+
+        let texture = &bsp.textures[1];
+        assert_eq!(texture.name, "external");
+        assert_eq!(texture.palette, None);
+        assert!(texture.pictures[0].data.is_empty());
+        assert_eq!(bsp.externally_referenced_textures(), vec!["external"]);
+
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert!(bsp.externally_referenced_textures().is_empty());
+        assert_eq!(bsp.version, BspVersion::Quake);
+        assert_eq!(bsp.textures[0].palette, None);
+        assert_eq!(bsp.textures[0].to_rgb(0), None);
+
+        data[0..4].copy_from_slice(&29i32.to_le_bytes());
+        let bsp = BspFile::parse(&mut Cursor::new(&data))?;
+        assert_eq!(bsp.externally_referenced_textures(), vec!["external"]);
+
+        data[0..4].copy_from_slice(&31i32.to_le_bytes());
+        assert!(BspFile::parse(&mut Cursor::new(&data)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_path_and_bytes() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        assert_eq!(BspFile::from_path("tests/files/povdmm4.bsp")?, bsp);
+        assert_eq!(
+            BspFile::from_bytes(&fs::read("tests/files/povdmm4.bsp")?)?,
+            bsp
+        );
+        assert!(BspFile::from_path("tests/files/missing.bsp").is_err());
+        assert!(BspFile::from_bytes(&[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_node_child() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let node = bsp
+            .nodes
+            .iter()
+            .find(|n| n.children[0] >= 0 && n.children[1] < 0)
+            .unwrap();
+
+        assert_eq!(node.child(0), NodeChild::Node(node.children[0] as u32));
+        assert_eq!(
+            node.child(1),
+            NodeChild::Leaf((-node.children[1] - 1) as u32)
+        );
+
+        let node = Node {
+            plane_index: 0,
+            children: [3, -1],
+            bounds: BoundingBox::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)),
+            face_indexes: 0..0,
+        };
+        assert_eq!(node.child(0), NodeChild::Node(3));
+        assert_eq!(node.child(1), NodeChild::Leaf(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_point_is_solid() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let origin = Vector3::new(-64.0, -640.0, -208.0);
+        assert!(!bsp.point_is_solid(origin));
+        assert_eq!(
+            bsp.leaves[bsp.leaf_at(origin).unwrap()].contents,
+            LeafContents::Empty
+        );
+
+        // just behind the floor below the light
+        let (_, distance) = bsp.raycast_faces(origin, -Vector3::unit_z()).unwrap();
+        assert!(bsp.point_is_solid(origin - Vector3::unit_z() * (distance + 4.0)));
+
+        assert!(bsp.point_is_solid(Vector3::new(100000.0, 0.0, 0.0)));
+        assert!(BspFile::default().point_is_solid(origin));
+        Ok(())
+    }
+
+    #[test]
+    fn test_leaves_with_content() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let water = bsp.leaves_with_content(LeafContents::Water);
+        assert!(!water.is_empty());
+        assert!(water.iter().all(|l| l.contents == LeafContents::Water));
+        assert_eq!(bsp.leaves_with_content(LeafContents::Solid).len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_total_marksurfaces() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        assert_eq!(bsp.leaves[0].face_count(), 0);
+        assert!(bsp.total_marksurfaces() > 0);
+        assert!(bsp.total_marksurfaces() as usize <= bsp.face_list.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_faces_front_to_back() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let world = crate::helpers::world_faces(&bsp);
+        let eye = Vector3::new(-64.0, -640.0, -208.0);
+
+        let mut indexes: Vec<_> = bsp
+            .faces_front_to_back(eye)
+            .map(|f| std::ptr::from_ref(f) as usize)
+            .collect();
+        assert_eq!(indexes.len(), world.len());
+        indexes.sort_unstable();
+        indexes.dedup();
+        assert_eq!(indexes.len(), world.len());
+        assert!(indexes
+            .iter()
+            .all(|&p| world.as_ptr_range().contains(&(p as *const Face))));
+
+        assert_eq!(BspFile::default().faces_front_to_back(eye).count(), 0);
+        Ok(())
+    }
 
     #[test]
     fn test_parse() -> Result<()> {
@@ -462,6 +3670,9 @@ mod tests {
             assert_eq!(bsp.planes.len(), 191);
             assert_eq!(bsp.faces.len(), 323);
             assert_eq!(bsp.models.len(), 5);
+            assert_eq!(bsp.nodes.len(), 135);
+            assert_eq!(bsp.leaves.len(), 77);
+            assert_eq!(bsp.face_list.len(), 376);
         }
         Ok(())
     }
@@ -0,0 +1,311 @@
+use crate::bsp::BoundingBox;
+use cgmath::Vector3;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypedValue {
+    Str(String),
+    Int(i32),
+    Float(f32),
+    Vec3(Vector3<f32>),
+    Color(Vector3<f32>),
+}
+
+impl TypedValue {
+    /// Parse a value by its shape: integers, floats and triplets of numbers.
+    /// Triplets of keys ending with `color` (e.g. `_color`) are parsed as colors.
+    pub fn parse(key: &str, value: &str) -> TypedValue {
+        if let Ok(value) = value.trim().parse() {
+            return TypedValue::Int(value);
+        }
+        if let Some(value) = parse_float(value) {
+            return TypedValue::Float(value);
+        }
+        match parse_vector3(value) {
+            Some(v) if key.ends_with("color") => TypedValue::Color(v),
+            Some(v) => TypedValue::Vec3(v),
+            None => TypedValue::Str(value.to_string()),
+        }
+    }
+}
+
+/// Typed accessors for the raw key/value pairs of an entity.
+pub trait EntityExt {
+    fn value(&self, key: &str) -> Option<&str>;
+
+    /// Underscore-prefixed keys (e.g. `_color`, `_minlight`) used as editor and compiler hints.
+    fn editor_keys(&self) -> HashMap<&str, &str>;
+
+    /// Entity as an entity lump block (`{ "key" "value" }`), with `classname` first,
+    /// then `origin`, then the remaining keys sorted.
+    fn to_entity_string(&self) -> String;
+
+    fn float(&self, key: &str) -> Option<f32> {
+        self.value(key).and_then(parse_float)
+    }
+
+    fn typed(&self, key: &str) -> Option<TypedValue> {
+        self.value(key).map(|value| TypedValue::parse(key, value))
+    }
+
+    fn classname(&self) -> Option<&str> {
+        self.value("classname")
+    }
+
+    fn origin(&self) -> Option<Vector3<f32>> {
+        self.value("origin").and_then(parse_vector3)
+    }
+
+    fn angle(&self) -> Option<f32> {
+        self.float("angle")
+    }
+
+    /// Seconds before e.g. a door returns or a trigger can fire again.
+    fn wait_secs(&self) -> Option<f32> {
+        self.float("wait")
+    }
+
+    /// Seconds before targets are fired.
+    fn delay_secs(&self) -> Option<f32> {
+        self.float("delay")
+    }
+
+    fn speed_val(&self) -> Option<f32> {
+        self.float("speed")
+    }
+
+    fn count_val(&self) -> Option<f32> {
+        self.float("count")
+    }
+
+    /// Index of the brush model (submodel) referenced as `"model" "*N"`.
+    fn submodel_index(&self) -> Option<usize> {
+        self.value("model")?.strip_prefix('*')?.parse().ok()
+    }
+
+    /// Path of an external model or sprite (e.g. `progs/flame.spr`), `None` for `*N` submodels.
+    fn model_path(&self) -> Option<&str> {
+        self.value("model")
+            .filter(|m| !m.is_empty() && !m.starts_with('*'))
+    }
+
+    fn model_is_sprite(&self) -> bool {
+        self.model_path()
+            .is_some_and(|m| m.to_ascii_lowercase().ends_with(".spr"))
+    }
+
+    /// Pitch, yaw and roll, used by e.g. cameras and spotlights.
+    fn mangle(&self) -> Option<Vector3<f32>> {
+        self.value("mangle").and_then(parse_vector3)
+    }
+
+    /// An `info_notnull` with a `mangle` is used as a camera (e.g. for intermission views).
+    fn is_camera(&self) -> bool {
+        self.classname() == Some("info_notnull") && self.value("mangle").is_some()
+    }
+}
+
+impl EntityExt for HashMap<String, String> {
+    fn value(&self, key: &str) -> Option<&str> {
+        self.get(key).map(String::as_str)
+    }
+
+    fn editor_keys(&self) -> HashMap<&str, &str> {
+        self.iter()
+            .filter(|(key, _)| key.starts_with('_'))
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect()
+    }
+
+    fn to_entity_string(&self) -> String {
+        entity_block(self, "")
+    }
+}
+
+/// Entity lump block with the key/value lines prefixed by `indent`.
+pub(crate) fn entity_block(entity: &HashMap<String, String>, indent: &str) -> String {
+    let key_rank = |key: &str| match key {
+        "classname" => 0,
+        "origin" => 1,
+        _ => 2,
+    };
+    let mut pairs: Vec<(&String, &String)> = entity.iter().collect();
+    pairs.sort_by(|a, b| key_rank(a.0).cmp(&key_rank(b.0)).then(a.0.cmp(b.0)));
+
+    let mut text = String::from("{\n");
+    for (key, value) in pairs {
+        text.push_str(&format!("{indent}\"{key}\" \"{value}\"\n"));
+    }
+    text.push_str("}\n");
+    text
+}
+
+pub fn parse_float(value: &str) -> Option<f32> {
+    value.trim().parse().ok()
+}
+
+pub fn parse_vector3(value: &str) -> Option<Vector3<f32>> {
+    let mut parts = value.split_whitespace().map(parse_float);
+
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Some(x)), Some(Some(y)), Some(Some(z)), None) => Some(Vector3::new(x, y, z)),
+        _ => None,
+    }
+}
+
+/// Parse six floats `"minx miny minz maxx maxy maxz"`, as used by e.g. `size` keys.
+pub fn parse_bbox(value: &str) -> Option<BoundingBox> {
+    let values: Vec<f32> = value
+        .split_whitespace()
+        .map(parse_float)
+        .collect::<Option<_>>()?;
+
+    match values[..] {
+        [min_x, min_y, min_z, max_x, max_y, max_z] => Some(BoundingBox::new(
+            Vector3::new(min_x, min_y, min_z),
+            Vector3::new(max_x, max_y, max_z),
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsp::{load_entities_file, BspFile};
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    #[test]
+    fn test_info_notnull() {
+        let mut entity = HashMap::from([
+            ("classname".to_string(), "info_notnull".to_string()),
+            ("origin".to_string(), "128 -64 32.5".to_string()),
+            ("angle".to_string(), "90".to_string()),
+        ]);
+        assert_eq!(entity.classname(), Some("info_notnull"));
+        assert_eq!(entity.origin(), Some(Vector3::new(128.0, -64.0, 32.5)));
+        assert_eq!(entity.angle(), Some(90.0));
+        assert!(!entity.is_camera());
+
+        entity.insert("mangle".to_string(), "10 270 0".to_string());
+        assert_eq!(entity.mangle(), Some(Vector3::new(10.0, 270.0, 0.0)));
+        assert!(entity.is_camera());
+
+        entity.insert("origin".to_string(), "1 2".to_string());
+        assert_eq!(entity.origin(), None);
+    }
+
+    #[test]
+    fn test_timing_fields() {
+        let door = HashMap::from([
+            ("classname".to_string(), "func_door".to_string()),
+            ("wait".to_string(), "-1".to_string()),
+            ("speed".to_string(), "300".to_string()),
+            ("delay".to_string(), "0.5".to_string()),
+        ]);
+        assert_eq!(door.wait_secs(), Some(-1.0));
+        assert_eq!(door.speed_val(), Some(300.0));
+        assert_eq!(door.delay_secs(), Some(0.5));
+        assert_eq!(door.count_val(), None);
+    }
+
+    #[test]
+    fn test_model_path() {
+        let mut entity = HashMap::from([
+            ("classname".to_string(), "misc_model".to_string()),
+            ("model".to_string(), "progs/flame.spr".to_string()),
+        ]);
+        assert_eq!(entity.model_path(), Some("progs/flame.spr"));
+        assert!(entity.model_is_sprite());
+
+        entity.insert("model".to_string(), "progs/flame2.mdl".to_string());
+        assert_eq!(entity.model_path(), Some("progs/flame2.mdl"));
+        assert!(!entity.model_is_sprite());
+
+        entity.insert("model".to_string(), "*3".to_string());
+        assert_eq!(entity.model_path(), None);
+        assert_eq!(entity.submodel_index(), Some(3));
+        assert!(!entity.model_is_sprite());
+    }
+
+    #[test]
+    fn test_parse_bbox() {
+        assert_eq!(
+            parse_bbox("-16 -16 -24 16 16 32.5"),
+            Some(BoundingBox::new(
+                Vector3::new(-16.0, -16.0, -24.0),
+                Vector3::new(16.0, 16.0, 32.5)
+            ))
+        );
+        assert_eq!(parse_bbox("-16 -16 -24"), None);
+        assert_eq!(parse_bbox("-16 -16 -24 16 16 x"), None);
+    }
+
+    #[test]
+    fn test_to_entity_string() -> anyhow::Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let worldspawn = bsp.worldspawn().unwrap();
+
+        let text = worldspawn.to_entity_string();
+        assert!(text.starts_with("{\n\"classname\" \"worldspawn\"\n"));
+        assert!(text.ends_with("}\n"));
+
+        let entities = load_entities_file(&mut text.as_bytes())?;
+        assert_eq!(entities, vec![worldspawn.clone()]);
+
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let light = bsp.entities.iter().find(|e| e.classname() == Some("light"));
+        let text = light.unwrap().to_entity_string();
+        assert!(text.starts_with("{\n\"classname\" \"light\"\n\"origin\" "));
+        assert_eq!(
+            bsp.entities_pretty().replace("    ", ""),
+            bsp.entities
+                .iter()
+                .map(|e| e.to_entity_string())
+                .collect::<String>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed() {
+        let light = HashMap::from([
+            ("classname".to_string(), "light".to_string()),
+            ("origin".to_string(), "1 2 3".to_string()),
+            ("light".to_string(), "300".to_string()),
+            ("wait".to_string(), "0.5".to_string()),
+            ("_color".to_string(), "1 0.5 0".to_string()),
+            ("message".to_string(), "\"Hello\" world".to_string()),
+        ]);
+        assert_eq!(
+            light.typed("origin"),
+            Some(TypedValue::Vec3(Vector3::new(1.0, 2.0, 3.0)))
+        );
+        assert_eq!(light.typed("light"), Some(TypedValue::Int(300)));
+        assert_eq!(light.typed("wait"), Some(TypedValue::Float(0.5)));
+        assert_eq!(
+            light.typed("_color"),
+            Some(TypedValue::Color(Vector3::new(1.0, 0.5, 0.0)))
+        );
+        assert_eq!(
+            light.typed("message"),
+            Some(TypedValue::Str("\"Hello\" world".to_string()))
+        );
+        assert_eq!(light.typed("target"), None);
+    }
+
+    #[test]
+    fn test_editor_keys() {
+        let light = HashMap::from([
+            ("classname".to_string(), "light".to_string()),
+            ("light".to_string(), "300".to_string()),
+            ("_color".to_string(), "1 0.5 0".to_string()),
+            ("_minlight".to_string(), "10".to_string()),
+        ]);
+        assert_eq!(
+            light.editor_keys(),
+            HashMap::from([("_color", "1 0.5 0"), ("_minlight", "10")])
+        );
+    }
+}
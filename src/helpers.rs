@@ -0,0 +1,993 @@
+use crate::bsp::{BspFile, BspVersion, Face, Model, Plane};
+use cgmath::{InnerSpace, Vector2, Vector3, Zero};
+use std::collections::HashSet;
+
+/// Vertices of a face in winding order, resolved through the edge list.
+pub fn face_vertices(bsp: &BspFile, face: &Face) -> Vec<Vector3<f32>> {
+    bsp.edge_list
+        .get(face.edge_indexes.clone())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|&index| bsp.directed_edge(index).map(|(start, _)| start))
+        .collect()
+}
+
+/// Centroid of a face (average of its vertices), `None` for faces without vertices.
+pub fn face_center(bsp: &BspFile, face: &Face) -> Option<Vector3<f32>> {
+    let vertices = face_vertices(bsp, face);
+
+    match vertices.is_empty() {
+        true => None,
+        false => Some(vertices.iter().sum::<Vector3<f32>>() / vertices.len() as f32),
+    }
+}
+
+/// Area of a face, computed by summing the triangles of its fan.
+/// Degenerate faces (less than 3 vertices) have an area of 0.
+pub fn face_area(bsp: &BspFile, face: &Face) -> f32 {
+    let vertices = face_vertices(bsp, face);
+
+    if vertices.len() < 3 {
+        return 0.0;
+    }
+
+    let origin = vertices[0];
+    let sum: Vector3<f32> = vertices[1..]
+        .windows(2)
+        .map(|w| (w[0] - origin).cross(w[1] - origin))
+        .sum();
+
+    sum.magnitude() / 2.0
+}
+
+/// Faces of the world model (model 0), i.e. excluding brush entities.
+pub fn world_faces(bsp: &BspFile) -> &[Face] {
+    match bsp.models.first() {
+        Some(model) => bsp
+            .faces
+            .get(model.face_indexes.clone())
+            .unwrap_or_default(),
+        None => &bsp.faces,
+    }
+}
+
+/// Indexes of the leaves potentially visible from a leaf, decoded from the run-length encoded
+/// visibility data (a zero byte is followed by the number of zero bytes). Leaves without
+/// visibility data see every leaf from 1 to the world `vis_leaf_count`.
+pub fn decompress_vis(bsp: &BspFile, leaf_index: usize) -> Vec<usize> {
+    let Some(leaf) = bsp.leaves.get(leaf_index) else {
+        return vec![];
+    };
+    let leaf_count = bsp.models.first().map_or(0, |m| m.vis_leaf_count);
+    if leaf.vis_offset < 0 {
+        return (1..=leaf_count).collect();
+    }
+
+    let row_size = leaf_count.div_ceil(8);
+    let mut row = Vec::with_capacity(row_size);
+    let mut bytes = bsp
+        .visibility
+        .get(leaf.vis_offset as usize..)
+        .unwrap_or_default()
+        .iter();
+
+    while row.len() < row_size {
+        match bytes.next() {
+            Some(0) => {
+                let count = bytes.next().copied().unwrap_or_default() as usize;
+                row.resize((row.len() + count).min(row_size), 0);
+            }
+            Some(&b) => row.push(b),
+            None => break,
+        }
+    }
+
+    // bit 0 is leaf 1, leaf 0 is never visible
+    (0..leaf_count)
+        .filter(|i| row.get(i / 8).is_some_and(|b| b & (1 << (i % 8)) != 0))
+        .map(|i| i + 1)
+        .collect()
+}
+
+/// Normal of a face, pointing out of the side the face is visible from.
+pub fn face_normal(bsp: &BspFile, face: &Face) -> Vector3<f32> {
+    match bsp.planes.get(face.plane_index) {
+        Some(plane) if face.is_front => plane.normal,
+        Some(plane) => -plane.normal,
+        None => Vector3::zero(),
+    }
+}
+
+/// Angle in degrees between the normals of two faces sharing an edge, `None` if they are not adjacent.
+pub fn dihedral_angle(bsp: &BspFile, face_a: usize, face_b: usize) -> Option<f32> {
+    if !bsp.adjacent_faces(face_a).contains(&face_b) {
+        return None;
+    }
+
+    let normal_a = face_normal(bsp, bsp.faces.get(face_a)?);
+    let normal_b = face_normal(bsp, bsp.faces.get(face_b)?);
+    Some(normal_a.dot(normal_b).clamp(-1.0, 1.0).acos().to_degrees())
+}
+
+/// Project a point onto the plane of a face along the plane normal.
+pub fn project_onto_face(bsp: &BspFile, face: &Face, point: Vector3<f32>) -> Vector3<f32> {
+    match bsp.planes.get(face.plane_index) {
+        Some(plane) => point - plane.normal * (plane.normal.dot(point) - plane.distance),
+        None => point,
+    }
+}
+
+/// Axis (0 = X, 1 = Y, 2 = Z) the face normal is most aligned with, as picked by `TextureAxisFromPlane`
+/// in the Quake tools. Ties are resolved in the same order: Z, X, Y.
+pub fn face_dominant_axis(bsp: &BspFile, face: &Face) -> usize {
+    let normal = face_normal(bsp, face);
+    let mut best_axis = 2;
+    let mut best = normal.z.abs();
+
+    for (axis, value) in [(0, normal.x), (1, normal.y)] {
+        if value.abs() > best {
+            best_axis = axis;
+            best = value.abs();
+        }
+    }
+
+    best_axis
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polygon {
+    pub vertices: Vec<Vector3<f32>>,
+    pub normal: Vector3<f32>,
+}
+
+/// World faces whose normal is within `max_slope_deg` of straight up.
+fn upward_faces(bsp: &BspFile, max_slope_deg: f32) -> impl Iterator<Item = &Face> {
+    let min_z = max_slope_deg.to_radians().cos();
+    world_faces(bsp)
+        .iter()
+        .filter(move |face| face_normal(bsp, face).z >= min_z)
+}
+
+/// Total area of world faces whose normal is within `max_slope_deg` of straight up.
+pub fn floor_area(bsp: &BspFile, max_slope_deg: f32) -> f32 {
+    upward_faces(bsp, max_slope_deg)
+        .map(|face| face_area(bsp, face))
+        .sum()
+}
+
+/// Upward facing world faces with an area of at least `min_area`, e.g. as input for navmesh generation.
+pub fn walkable_polygons(bsp: &BspFile, max_slope_deg: f32, min_area: f32) -> Vec<Polygon> {
+    upward_faces(bsp, max_slope_deg)
+        .filter(|face| face_area(bsp, face) >= min_area)
+        .map(|face| Polygon {
+            vertices: face_vertices(bsp, face),
+            normal: face_normal(bsp, face),
+        })
+        .collect()
+}
+
+/// Unique edge segments of all world faces, e.g. for wireframe rendering.
+pub fn to_wireframe(bsp: &BspFile) -> Vec<[Vector3<f32>; 2]> {
+    let mut seen = HashSet::new();
+    let mut segments = Vec::new();
+
+    for face in world_faces(bsp) {
+        for &index in bsp
+            .edge_list
+            .get(face.edge_indexes.clone())
+            .unwrap_or_default()
+        {
+            let edge_index = index.unsigned_abs() as usize;
+
+            if let Some(edge) = bsp.edges.get(edge_index) {
+                if seen.insert(edge_index) {
+                    segments.push([edge.0, edge.1]);
+                }
+            }
+        }
+    }
+
+    segments
+}
+
+const CLIP_EPSILON: f32 = 0.01;
+
+/// Split a polygon by a plane into the pieces in front of and behind the plane.
+/// Vertices on the plane are kept in both pieces; pieces with less than 3 vertices are empty.
+pub fn clip_polygon(
+    poly: &[Vector3<f32>],
+    plane: &Plane,
+) -> (Vec<Vector3<f32>>, Vec<Vector3<f32>>) {
+    let mut front = Vec::new();
+    let mut back = Vec::new();
+    let distance = |v: &Vector3<f32>| plane.normal.dot(*v) - plane.distance;
+
+    for (i, p) in poly.iter().enumerate() {
+        let q = &poly[(i + 1) % poly.len()];
+        let (dp, dq) = (distance(p), distance(q));
+
+        if dp >= -CLIP_EPSILON {
+            front.push(*p);
+        }
+        if dp <= CLIP_EPSILON {
+            back.push(*p);
+        }
+
+        let crosses =
+            (dp > CLIP_EPSILON && dq < -CLIP_EPSILON) || (dp < -CLIP_EPSILON && dq > CLIP_EPSILON);
+        if crosses {
+            let mid = p + (q - p) * (dp / (dp - dq));
+            front.push(mid);
+            back.push(mid);
+        }
+    }
+
+    for piece in [&mut front, &mut back] {
+        if piece.len() < 3 {
+            piece.clear();
+        }
+    }
+
+    (front, back)
+}
+
+/// Texture space bounds (min, max) of a face, from its texture info vectors.
+pub fn face_texture_extents(bsp: &BspFile, face: &Face) -> Option<(Vector2<f32>, Vector2<f32>)> {
+    let info = bsp.texture_info.get(face.texture_info_index)?;
+    let coords: Vec<Vector2<f32>> = face_vertices(bsp, face)
+        .iter()
+        .map(|v| {
+            Vector2::new(
+                v.dot(info.vector_s) + info.dist_s,
+                v.dot(info.vector_t) + info.dist_t,
+            )
+        })
+        .collect();
+
+    let first = *coords.first()?;
+    Some(coords.iter().fold((first, first), |(min, max), c| {
+        (
+            Vector2::new(min.x.min(c.x), min.y.min(c.y)),
+            Vector2::new(max.x.max(c.x), max.y.max(c.y)),
+        )
+    }))
+}
+
+/// Number of times the texture tiles across a face in the S and T directions.
+pub fn texture_repeats(bsp: &BspFile, face: &Face) -> Vector2<f32> {
+    let texture = bsp
+        .texture_info
+        .get(face.texture_info_index)
+        .and_then(|info| bsp.textures.get(info.texture_index))
+        .filter(|t| t.width > 0 && t.height > 0);
+
+    match (texture, face_texture_extents(bsp, face)) {
+        (Some(texture), Some((min, max))) => Vector2::new(
+            (max.x - min.x) / texture.width as f32,
+            (max.y - min.y) / texture.height as f32,
+        ),
+        _ => Vector2::zero(),
+    }
+}
+
+/// Size in luxels (width, height) of a face lightmap, with one luxel per 16 texels.
+pub fn face_lightmap_size(bsp: &BspFile, face: &Face) -> Option<(u32, u32)> {
+    if !face.has_lightmap() {
+        return None;
+    }
+
+    let (min, max) = face_texture_extents(bsp, face)?;
+    let size = |min: f32, max: f32| ((max / 16.0).ceil() - (min / 16.0).floor()) as u32 + 1;
+    Some((size(min.x, max.x), size(min.y, max.y)))
+}
+
+/// Bytes per lightmap luxel: one for Quake, three (RGB) for Half-Life.
+fn lightmap_channels(bsp: &BspFile) -> usize {
+    match bsp.version {
+        BspVersion::Quake => 1,
+        BspVersion::HalfLife => 3,
+    }
+}
+
+/// Lightmap luxels of a face (first light style), as RGB triplets for Half-Life maps.
+pub fn face_lightmap<'a>(bsp: &'a BspFile, face: &Face) -> Option<&'a [u8]> {
+    let (width, height) = face_lightmap_size(bsp, face)?;
+    let start = face.light_map as usize;
+    let len = (width * height) as usize * lightmap_channels(bsp);
+    bsp.light_maps.get(start..start + len)
+}
+
+/// Average brightness of a face lightmap from 0 to 1, `None` for faces without a lightmap.
+/// Half-Life lightmaps are averaged over their color channels.
+pub fn face_brightness(bsp: &BspFile, face: &Face) -> Option<f32> {
+    let luxels = face_lightmap(bsp, face).filter(|l| !l.is_empty())?;
+    let sum: u64 = luxels.iter().map(|&l| l as u64).sum();
+    Some(sum as f32 / luxels.len() as f32 / 255.0)
+}
+
+/// All face lightmaps packed into an atlas using shelf packing, grayscale for Quake maps.
+#[cfg(feature = "image")]
+pub fn export_lightmap_atlas(bsp: &BspFile) -> anyhow::Result<image::RgbImage> {
+    const ATLAS_WIDTH: u32 = 512;
+
+    let mut lightmaps: Vec<(u32, u32, &[u8])> = bsp
+        .faces
+        .iter()
+        .filter_map(|face| {
+            let (width, height) = face_lightmap_size(bsp, face)?;
+            Some((width, height, face_lightmap(bsp, face)?))
+        })
+        .collect();
+    lightmaps.sort_by_key(|(_, height, _)| std::cmp::Reverse(*height));
+
+    if let Some((width, _, _)) = lightmaps.iter().find(|(width, _, _)| *width > ATLAS_WIDTH) {
+        return Err(anyhow::anyhow!(
+            "Lightmap width {width} exceeds atlas width {ATLAS_WIDTH}"
+        ));
+    }
+
+    // shelf packing: place left to right, start a new shelf when the row is full
+    let mut positions = Vec::with_capacity(lightmaps.len());
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+    for (width, height, _) in &lightmaps {
+        if x + width > ATLAS_WIDTH {
+            x = 0;
+            y += shelf_height;
+            shelf_height = 0;
+        }
+        positions.push((x, y));
+        x += width;
+        shelf_height = shelf_height.max(*height);
+    }
+
+    let mut atlas = image::RgbImage::new(ATLAS_WIDTH, (y + shelf_height).max(1));
+    for ((width, _, luxels), (x, y)) in lightmaps.iter().zip(positions) {
+        for (i, luxel) in luxels.chunks(lightmap_channels(bsp)).enumerate() {
+            let (lx, ly) = (i as u32 % width, i as u32 / width);
+            let color = match *luxel {
+                [r, g, b] => [r, g, b],
+                _ => [luxel[0]; 3],
+            };
+            atlas.put_pixel(x + lx, y + ly, image::Rgb(color));
+        }
+    }
+
+    Ok(atlas)
+}
+
+/// Fraction of faces that have a lightmap, from 0 to 1.
+pub fn lightmap_coverage(bsp: &BspFile) -> f32 {
+    if bsp.faces.is_empty() {
+        return 0.0;
+    }
+
+    let lit_count = bsp.faces.iter().filter(|f| f.has_lightmap()).count();
+    lit_count as f32 / bsp.faces.len() as f32
+}
+
+bitflags::bitflags! {
+    /// Surface properties of a face, see [`face_surface_flags`].
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct SurfaceFlags: u32 {
+        const SKY = 1;
+        const LIQUID = 1 << 1;
+        const TRANSPARENT = 1 << 2;
+        const NOLIGHTMAP = 1 << 3;
+        const ANIMATED = 1 << 4;
+    }
+}
+
+/// Surface flags of a face, combined from the texture info flags, the texture name conventions
+/// (`sky*`, `*liquid`, `{fence`, `+animated`) and the lightmap offset.
+pub fn face_surface_flags(bsp: &BspFile, face: &Face) -> SurfaceFlags {
+    let mut flags = SurfaceFlags::empty();
+    let info = bsp.texture_info.get(face.texture_info_index);
+
+    if info.is_some_and(|i| i.animated) || !face.has_lightmap() {
+        flags |= SurfaceFlags::NOLIGHTMAP;
+    }
+
+    let name = info
+        .and_then(|i| bsp.textures.get(i.texture_index))
+        .map(|t| t.name.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    if name.starts_with("sky") {
+        flags |= SurfaceFlags::SKY;
+    }
+    if name.starts_with('*') {
+        flags |= SurfaceFlags::LIQUID;
+    }
+    if name.starts_with('{') {
+        flags |= SurfaceFlags::TRANSPARENT;
+    }
+    if name.starts_with('+') {
+        flags |= SurfaceFlags::ANIMATED;
+    }
+
+    flags
+}
+
+/// Vertices of a model's faces, offset by the model origin (non-zero for e.g. rotating brush entities).
+pub fn model_vertices(bsp: &BspFile, model: &Model) -> Vec<Vector3<f32>> {
+    bsp.faces
+        .get(model.face_indexes.clone())
+        .unwrap_or_default()
+        .iter()
+        .flat_map(|face| face_vertices(bsp, face))
+        .map(|v| v + model.origin)
+        .collect()
+}
+
+/// Extreme points of the convex hull of a model's face vertices.
+pub fn model_convex_hull(bsp: &BspFile, model: &Model) -> Vec<Vector3<f32>> {
+    let mut points: Vec<Vector3<f64>> = Vec::new();
+
+    for face in bsp
+        .faces
+        .get(model.face_indexes.clone())
+        .unwrap_or_default()
+    {
+        for v in face_vertices(bsp, face) {
+            let v = v.cast::<f64>().unwrap();
+            if !points.iter().any(|p| (p - v).magnitude() <= HULL_EPSILON) {
+                points.push(v);
+            }
+        }
+    }
+
+    convex_hull(&points)
+        .into_iter()
+        .map(|i| points[i].cast::<f32>().unwrap())
+        .collect()
+}
+
+const HULL_EPSILON: f64 = 0.01;
+
+/// Indexes of the extreme points of a 3D point set.
+///
+/// The hull facets are found incrementally, after which the extreme points are
+/// the strict 2D hull vertices of the points lying on each supporting plane.
+/// This keeps points on hull faces or edges (e.g. T-junction splits) out of the result.
+fn convex_hull(points: &[Vector3<f64>]) -> Vec<usize> {
+    let eps = HULL_EPSILON;
+
+    if points.len() < 3 {
+        return (0..points.len()).collect();
+    }
+
+    // initial tetrahedron
+    let i0 = 0;
+    let i1 = farthest(points, |p| (p - points[i0]).magnitude());
+    let line = (points[i1] - points[i0]).normalize();
+    let i2 = farthest(points, |p| (p - points[i0]).cross(line).magnitude());
+
+    if (points[i2] - points[i0]).cross(line).magnitude() <= eps {
+        let along_line = |p: &Vector3<f64>| line.dot(*p);
+        let (min, max) = (
+            farthest(points, |p| -along_line(p)),
+            farthest(points, along_line),
+        );
+        return vec![min.min(max), min.max(max)];
+    }
+
+    let base_normal = (points[i1] - points[i0])
+        .cross(points[i2] - points[i0])
+        .normalize();
+    let i3 = farthest(points, |p| base_normal.dot(p - points[i0]).abs());
+
+    if base_normal.dot(points[i3] - points[i0]).abs() <= eps {
+        return extremes_on_plane(points, &(0..points.len()).collect::<Vec<_>>(), base_normal);
+    }
+
+    let plane_of = |[a, b, c]: [usize; 3]| {
+        let normal = (points[b] - points[a]).cross(points[c] - points[a]);
+        let normal = normal / normal.magnitude().max(f64::EPSILON);
+        (normal, normal.dot(points[a]))
+    };
+
+    let mut faces: Vec<[usize; 3]> = vec![[i0, i1, i2], [i0, i2, i3], [i0, i3, i1], [i1, i3, i2]];
+    let center = (points[i0] + points[i1] + points[i2] + points[i3]) / 4.0;
+    for face in faces.iter_mut() {
+        let (normal, dist) = plane_of(*face);
+        if normal.dot(center) > dist {
+            face.swap(1, 2);
+        }
+    }
+
+    for (i, p) in points.iter().enumerate() {
+        let (visible, hidden): (Vec<[usize; 3]>, Vec<[usize; 3]>) = faces.iter().partition(|&&f| {
+            let (normal, dist) = plane_of(f);
+            normal.dot(*p) - dist > eps
+        });
+
+        if visible.is_empty() {
+            continue;
+        }
+
+        let edges: Vec<(usize, usize)> = visible
+            .iter()
+            .flat_map(|&[a, b, c]| [(a, b), (b, c), (c, a)])
+            .collect();
+        let horizon = edges.iter().filter(|(a, b)| !edges.contains(&(*b, *a)));
+
+        faces = hidden;
+        faces.extend(horizon.map(|&(a, b)| [a, b, i]));
+    }
+
+    // collect the extreme points on each supporting plane
+    let mut planes: Vec<(Vector3<f64>, f64)> = Vec::new();
+    let mut extremes: Vec<usize> = Vec::new();
+
+    for face in faces {
+        let (normal, dist) = plane_of(face);
+
+        let is_known = planes
+            .iter()
+            .any(|(n, d)| (n - normal).magnitude() <= 1e-6 && (d - dist).abs() <= eps);
+        let is_supporting = points.iter().all(|p| normal.dot(*p) - dist <= eps);
+
+        if is_known || !is_supporting {
+            continue;
+        }
+
+        planes.push((normal, dist));
+        let on_plane: Vec<usize> = (0..points.len())
+            .filter(|&i| (normal.dot(points[i]) - dist).abs() <= eps)
+            .collect();
+        extremes.extend(extremes_on_plane(points, &on_plane, normal));
+    }
+
+    extremes.sort_unstable();
+    extremes.dedup();
+    extremes
+}
+
+fn farthest<F>(points: &[Vector3<f64>], distance: F) -> usize
+where
+    F: Fn(&Vector3<f64>) -> f64,
+{
+    (0..points.len())
+        .max_by(|&a, &b| distance(&points[a]).total_cmp(&distance(&points[b])))
+        .unwrap_or_default()
+}
+
+/// Strict 2D hull (monotone chain) of the given coplanar points.
+fn extremes_on_plane(
+    points: &[Vector3<f64>],
+    indexes: &[usize],
+    normal: Vector3<f64>,
+) -> Vec<usize> {
+    let helper = if normal.x.abs() < 0.9 {
+        Vector3::unit_x()
+    } else {
+        Vector3::unit_y()
+    };
+    let u = normal.cross(helper).normalize();
+    let v = normal.cross(u);
+
+    let mut sorted: Vec<(f64, f64, usize)> = indexes
+        .iter()
+        .map(|&i| (u.dot(points[i]), v.dot(points[i]), i))
+        .collect();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+
+    // turns counter-clockwise by more than epsilon (distance of b from line a-c)
+    let is_convex = |a: (f64, f64, usize), b: (f64, f64, usize), c: (f64, f64, usize)| {
+        let cross = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+        let len = ((c.0 - a.0).powi(2) + (c.1 - a.1).powi(2)).sqrt();
+        cross > HULL_EPSILON * len
+    };
+
+    let mut hull: Vec<(f64, f64, usize)> = Vec::new();
+    for pass in [sorted.clone(), sorted.into_iter().rev().collect()] {
+        let start = hull.len();
+        for p in pass {
+            while hull.len() >= start + 2
+                && !is_convex(hull[hull.len() - 2], hull[hull.len() - 1], p)
+            {
+                hull.pop();
+            }
+            hull.push(p);
+        }
+        hull.pop();
+    }
+
+    hull.into_iter().map(|(_, _, i)| i).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsp::{Edge, LeafContents, Texture, TextureInfo};
+    use anyhow::Result;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    fn rectangle_bsp(width: f32, height: f32) -> BspFile {
+        let a = Vector3::new(0.0, 0.0, 0.0);
+        let b = Vector3::new(width, 0.0, 0.0);
+        let c = Vector3::new(width, height, 0.0);
+        let d = Vector3::new(0.0, height, 0.0);
+
+        BspFile {
+            edges: vec![Edge(a, a), Edge(a, b), Edge(b, c), Edge(d, c), Edge(d, a)],
+            edge_list: vec![1, 2, -3, 4],
+            faces: vec![Face {
+                edge_indexes: 0..4,
+                ..Face::default()
+            }],
+            ..BspFile::default()
+        }
+    }
+
+    #[test]
+    fn test_face_area() {
+        let bsp = rectangle_bsp(64.0, 32.0);
+        let face = &bsp.faces[0];
+        assert_eq!(face_vertices(&bsp, face).len(), 4);
+        assert_eq!(face_area(&bsp, face), 64.0 * 32.0);
+        assert_eq!(face_center(&bsp, face), Some(Vector3::new(32.0, 16.0, 0.0)));
+
+        let degenerate = Face {
+            edge_indexes: 0..2,
+            ..Face::default()
+        };
+        assert_eq!(face_area(&bsp, &degenerate), 0.0);
+    }
+
+    #[test]
+    fn test_lightmap_coverage() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let coverage = lightmap_coverage(&bsp);
+        assert!(coverage > 0.0 && coverage < 1.0);
+        assert_eq!(lightmap_coverage(&BspFile::default()), 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_face_surface_flags() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let is_sky = |face: &&Face| {
+            let info = &bsp.texture_info[face.texture_info_index];
+            bsp.textures[info.texture_index].name == "sky4"
+        };
+
+        let sky_face = bsp.faces.iter().find(is_sky).unwrap();
+        let flags = face_surface_flags(&bsp, sky_face);
+        assert!(flags.contains(SurfaceFlags::SKY | SurfaceFlags::NOLIGHTMAP));
+        assert!(!flags.contains(SurfaceFlags::LIQUID));
+
+        let wall_face = bsp.faces.iter().find(|f| !is_sky(f)).unwrap();
+        assert!(!face_surface_flags(&bsp, wall_face).contains(SurfaceFlags::SKY));
+        Ok(())
+    }
+
+    fn polygons_bsp(polygons: &[Vec<[f32; 3]>]) -> BspFile {
+        let mut bsp = BspFile {
+            edges: vec![Edge(
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 0.0),
+            )],
+            ..BspFile::default()
+        };
+
+        for polygon in polygons {
+            let start = bsp.edge_list.len();
+            for &v in polygon {
+                bsp.edge_list.push(bsp.edges.len() as i32);
+                bsp.edges.push(Edge(v.into(), v.into()));
+            }
+            bsp.faces.push(Face {
+                edge_indexes: start..bsp.edge_list.len(),
+                ..Face::default()
+            });
+        }
+
+        bsp.models.push(Model {
+            bound: (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)),
+            origin: Vector3::new(0.0, 0.0, 0.0),
+            node_indexes: [0; 4],
+            vis_leaf_count: 0,
+            face_indexes: 0..bsp.faces.len(),
+        });
+        bsp
+    }
+
+    #[test]
+    fn test_model_convex_hull() -> Result<()> {
+        // box with an extra vertex splitting an edge of the top face
+        let (x0, y0, z0, x1, y1, z1) = (0.0, 0.0, 0.0, 64.0, 32.0, 16.0);
+        let bsp = polygons_bsp(&[
+            vec![
+                [x0, y0, z1],
+                [32.0, y0, z1],
+                [x1, y0, z1],
+                [x1, y1, z1],
+                [x0, y1, z1],
+            ],
+            vec![[x0, y0, z0], [x0, y1, z0], [x1, y1, z0], [x1, y0, z0]],
+            vec![
+                [x0, y0, z0],
+                [x1, y0, z0],
+                [x1, y0, z1],
+                [32.0, y0, z1],
+                [x0, y0, z1],
+            ],
+            vec![[x0, y1, z0], [x0, y1, z1], [x1, y1, z1], [x1, y1, z0]],
+            vec![[x0, y0, z0], [x0, y0, z1], [x0, y1, z1], [x0, y1, z0]],
+            vec![[x1, y0, z0], [x1, y1, z0], [x1, y1, z1], [x1, y0, z1]],
+        ]);
+        let hull = model_convex_hull(&bsp, &bsp.models[0]);
+        assert_eq!(hull.len(), 8);
+        assert!(!hull.contains(&Vector3::new(32.0, y0, z1)));
+
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        assert_eq!(model_convex_hull(&bsp, &bsp.models[1]).len(), 8);
+        assert!(model_convex_hull(&bsp, &bsp.models[0]).len() >= 8);
+        Ok(())
+    }
+
+    #[test]
+    fn test_face_dominant_axis() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let floor = bsp
+            .faces
+            .iter()
+            .find(|f| face_normal(&bsp, f).z > 0.99)
+            .unwrap();
+        assert_eq!(face_dominant_axis(&bsp, floor), 2);
+
+        let wall = bsp
+            .faces
+            .iter()
+            .find(|f| face_normal(&bsp, f).x.abs() > 0.99)
+            .unwrap();
+        assert_eq!(face_dominant_axis(&bsp, wall), 0);
+
+        let diagonal = BspFile {
+            planes: vec![Plane {
+                normal: Vector3::new(0.0, 1.0, 1.0).normalize(),
+                distance: 0.0,
+                kind: 0,
+            }],
+            faces: vec![Face::default()],
+            ..BspFile::default()
+        };
+        assert_eq!(face_dominant_axis(&diagonal, &diagonal.faces[0]), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dihedral_angle() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let (floor, wall) = (0..bsp.faces.len())
+            .filter(|&i| face_normal(&bsp, &bsp.faces[i]).z > 0.99)
+            .find_map(|floor| {
+                let wall = bsp
+                    .adjacent_faces(floor)
+                    .into_iter()
+                    .find(|&i| face_normal(&bsp, &bsp.faces[i]).z.abs() < 0.01)?;
+                Some((floor, wall))
+            })
+            .unwrap();
+
+        let angle = dihedral_angle(&bsp, floor, wall).unwrap();
+        assert!((angle - 90.0).abs() < 0.01);
+        assert_eq!(
+            dihedral_angle(&bsp, floor, wall),
+            dihedral_angle(&bsp, wall, floor)
+        );
+
+        let far = (0..bsp.faces.len())
+            .find(|&i| i != floor && !bsp.adjacent_faces(floor).contains(&i))
+            .unwrap();
+        assert_eq!(dihedral_angle(&bsp, floor, far), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_onto_face() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+
+        for face in bsp.faces.iter().step_by(7) {
+            let plane = &bsp.planes[face.plane_index];
+            let projected = project_onto_face(&bsp, face, Vector3::new(12.0, -340.5, 97.0));
+            assert!((plane.normal.dot(projected) - plane.distance).abs() < 0.01);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompress_vis() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let leaf_count = bsp.models[0].vis_leaf_count;
+
+        let (index, _) = bsp
+            .leaves
+            .iter()
+            .enumerate()
+            .find(|(_, l)| l.contents == LeafContents::Empty && l.vis_offset >= 0)
+            .unwrap();
+        let visible = decompress_vis(&bsp, index);
+        assert!(visible.contains(&index));
+        assert!(visible.len() < leaf_count);
+        assert!(visible.iter().all(|&i| i >= 1 && i <= leaf_count));
+
+        let mut bsp = bsp;
+        bsp.leaves[index].vis_offset = -1;
+        assert_eq!(
+            decompress_vis(&bsp, index),
+            (1..=leaf_count).collect::<Vec<_>>()
+        );
+        assert_eq!(decompress_vis(&bsp, bsp.leaves.len()), Vec::<usize>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn test_floor_area() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let flat = floor_area(&bsp, 0.0);
+        let sloped = floor_area(&bsp, 45.0);
+
+        assert!(flat > 0.0);
+        assert!(sloped >= flat);
+        assert!(floor_area(&bsp, 180.0) > sloped);
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_vertices() {
+        let mut bsp = polygons_bsp(&[vec![[0.0, 0.0, 0.0], [16.0, 0.0, 0.0], [16.0, 16.0, 0.0]]]);
+        bsp.models[0].origin = Vector3::new(100.0, 200.0, 300.0);
+
+        assert_eq!(
+            model_vertices(&bsp, &bsp.models[0]),
+            vec![
+                Vector3::new(100.0, 200.0, 300.0),
+                Vector3::new(116.0, 200.0, 300.0),
+                Vector3::new(116.0, 216.0, 300.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walkable_polygons() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let polygons = walkable_polygons(&bsp, 30.0, 64.0);
+        let min_z = 30f32.to_radians().cos();
+
+        assert!(!polygons.is_empty());
+        assert!(polygons.len() < walkable_polygons(&bsp, 30.0, 0.0).len());
+        for polygon in polygons {
+            assert!(polygon.normal.z >= min_z);
+            assert!(polygon.vertices.len() >= 3);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_wireframe() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/povdmm4.bsp")?)?;
+        let segments = to_wireframe(&bsp);
+
+        assert!(!segments.is_empty());
+        assert!(segments.len() <= bsp.edges.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_clip_polygon() {
+        let square = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(64.0, 0.0, 0.0),
+            Vector3::new(64.0, 64.0, 0.0),
+            Vector3::new(0.0, 64.0, 0.0),
+        ];
+        let plane = Plane {
+            normal: Vector3::new(1.0, 0.0, 0.0),
+            distance: 32.0,
+            kind: 0,
+        };
+
+        let (front, back) = clip_polygon(&square, &plane);
+        assert_eq!(
+            front,
+            vec![
+                Vector3::new(32.0, 0.0, 0.0),
+                Vector3::new(64.0, 0.0, 0.0),
+                Vector3::new(64.0, 64.0, 0.0),
+                Vector3::new(32.0, 64.0, 0.0),
+            ]
+        );
+        assert_eq!(
+            back,
+            vec![
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(32.0, 0.0, 0.0),
+                Vector3::new(32.0, 64.0, 0.0),
+                Vector3::new(0.0, 64.0, 0.0),
+            ]
+        );
+
+        // touching the plane with an edge
+        let plane = Plane {
+            distance: 64.0,
+            ..plane
+        };
+        let (front, back) = clip_polygon(&square, &plane);
+        assert!(front.is_empty());
+        assert_eq!(back, square.to_vec());
+    }
+
+    #[test]
+    fn test_face_lightmap() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+
+        for face in &bsp.faces {
+            match face_lightmap_size(&bsp, face) {
+                Some((width, height)) => {
+                    assert!((1..=18).contains(&width) && (1..=18).contains(&height));
+                    assert_eq!(
+                        face_lightmap(&bsp, face).map(|l| l.len()),
+                        Some((width * height) as usize)
+                    );
+                }
+                None => assert!(!face.has_lightmap()),
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_export_lightmap_atlas() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let atlas = export_lightmap_atlas(&bsp)?;
+
+        assert_eq!(atlas.width(), 512);
+        assert!(atlas.height() > 1);
+        assert!(atlas.pixels().any(|p| p.0 != [0, 0, 0]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_texture_repeats() {
+        let mut bsp = polygons_bsp(&[vec![
+            [0.0, 0.0, 0.0],
+            [64.0, 0.0, 0.0],
+            [64.0, 128.0, 0.0],
+            [0.0, 128.0, 0.0],
+        ]]);
+        bsp.textures.push(Texture {
+            width: 64,
+            height: 64,
+            ..Texture::default()
+        });
+        bsp.texture_info.push(TextureInfo {
+            vector_s: Vector3::new(1.0, 0.0, 0.0),
+            dist_s: 0.0,
+            vector_t: Vector3::new(0.0, 1.0, 0.0),
+            dist_t: 0.0,
+            texture_index: 0,
+            animated: false,
+        });
+
+        assert_eq!(texture_repeats(&bsp, &bsp.faces[0]), Vector2::new(1.0, 2.0));
+
+        bsp.texture_info[0].vector_s = Vector3::new(0.5, 0.0, 0.0);
+        assert_eq!(texture_repeats(&bsp, &bsp.faces[0]), Vector2::new(0.5, 2.0));
+    }
+
+    #[test]
+    fn test_face_brightness() -> Result<()> {
+        let bsp = BspFile::parse(&mut fs::File::open("tests/files/dm3_gpl.bsp")?)?;
+        let lit_face = bsp.faces.iter().find(|f| f.has_lightmap()).unwrap();
+        let unlit_face = bsp.faces.iter().find(|f| !f.has_lightmap()).unwrap();
+
+        let brightness = face_brightness(&bsp, lit_face).unwrap();
+        assert!((0.0..=1.0).contains(&brightness));
+        assert_eq!(face_brightness(&bsp, unlit_face), None);
+        Ok(())
+    }
+}
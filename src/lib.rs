@@ -1,2 +1,5 @@
 pub mod bsp;
+pub mod entity;
+pub mod helpers;
+pub mod pak;
 pub mod parse;
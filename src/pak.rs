@@ -0,0 +1,142 @@
+// specification: https://quakewiki.org/wiki/.pak
+use crate::bsp::BspFile;
+use crate::parse::*;
+use anyhow::{anyhow as e, Result};
+use std::io::{Read, Seek, SeekFrom};
+
+const PAK_MAGIC: &[u8; 4] = b"PACK";
+const SIZE_DIRECTORY_ENTRY: usize = 56 + 4 + 4;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PakEntry {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// List all entries in the PAK directory.
+pub fn list_entries<R>(r: &mut R) -> Result<Vec<PakEntry>>
+where
+    R: Read + Seek,
+{
+    r.seek(SeekFrom::Start(0))?;
+
+    if &read_string!(r, 4) != PAK_MAGIC {
+        return Err(e!("Invalid PAK file, missing PACK header"));
+    }
+
+    let dir_offset = r.read_ulong()?;
+    let dir_size = r.read_ulong()?;
+    let stream_len = r.seek(SeekFrom::End(0))?;
+    if dir_offset as u64 + dir_size as u64 > stream_len {
+        return Err(e!(
+            "PAK directory at {dir_offset} with size {dir_size} exceeds file size {stream_len}"
+        ));
+    }
+    let count = dir_size as usize / SIZE_DIRECTORY_ENTRY;
+
+    r.seek(SeekFrom::Start(dir_offset as u64))?;
+    let mut entries = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        entries.push(PakEntry {
+            name: from_cstring(&read_string!(r, 56))?,
+            offset: r.read_ulong()?,
+            size: r.read_ulong()?,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// List the `.bsp` entries in the PAK directory.
+pub fn list_bsps<R>(r: &mut R) -> Result<Vec<PakEntry>>
+where
+    R: Read + Seek,
+{
+    Ok(list_entries(r)?
+        .into_iter()
+        .filter(|entry| entry.name.to_ascii_lowercase().ends_with(".bsp"))
+        .collect())
+}
+
+/// Read the contents of the entry named `name`.
+pub fn extract<R>(r: &mut R, name: &str) -> Result<Vec<u8>>
+where
+    R: Read + Seek,
+{
+    let entry = find_entry(r, name)?;
+    let mut data = vec![0; entry.size as usize];
+    r.seek(SeekFrom::Start(entry.offset as u64))?;
+    r.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Parse the BSP entry named `name` in place, without extracting it.
+pub fn parse_bsp<R>(r: &mut R, name: &str) -> Result<BspFile>
+where
+    R: Read + Seek,
+{
+    let entry = find_entry(r, name)?;
+    BspFile::parse_at(r, entry.offset as u64)
+}
+
+fn find_entry<R>(r: &mut R, name: &str) -> Result<PakEntry>
+where
+    R: Read + Seek,
+{
+    list_entries(r)?
+        .into_iter()
+        .find(|entry| entry.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| e!("PAK entry not found: {name}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_pak() -> Result<()> {
+        let pak = &mut fs::File::open("tests/files/povdmm4.pak")?;
+        let bsp_data = fs::read("tests/files/povdmm4.bsp")?;
+
+        assert_eq!(list_entries(pak)?.len(), 2);
+        assert_eq!(
+            list_bsps(pak)?,
+            vec![PakEntry {
+                name: "maps/povdmm4.bsp".to_string(),
+                offset: 12,
+                size: bsp_data.len() as u32,
+            }]
+        );
+
+        assert_eq!(extract(pak, "maps/povdmm4.bsp")?, bsp_data);
+        assert!(extract(pak, "maps/e1m1.bsp").is_err());
+
+        assert_eq!(
+            parse_bsp(pak, "maps/povdmm4.bsp")?,
+            BspFile::parse(&mut Cursor::new(bsp_data))?
+        );
+
+        assert!(list_entries(&mut Cursor::new(b"IWAD".to_vec())).is_err());
+
+        let mut corrupt = fs::read("tests/files/povdmm4.pak")?;
+        corrupt[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            list_entries(&mut Cursor::new(&corrupt))
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "PAK directory at {} with size {} exceeds file size {}",
+                u32::from_le_bytes(corrupt[4..8].try_into()?),
+                u32::MAX,
+                corrupt.len()
+            )
+        );
+        Ok(())
+    }
+}
@@ -21,6 +21,7 @@ pub trait CRead {
     fn read_ulong(&mut self) -> io::Result<u32>;
     fn read_float(&mut self) -> io::Result<f32>;
     fn read_vector3_float(&mut self) -> io::Result<(f32, f32, f32)>;
+    fn read_vector3_short(&mut self) -> io::Result<(i16, i16, i16)>;
 }
 
 impl<T> CRead for T
@@ -55,6 +56,13 @@ where
             self.read_f32::<LittleEndian>()?,
         ))
     }
+    fn read_vector3_short(&mut self) -> io::Result<(i16, i16, i16)> {
+        Ok((
+            self.read_i16::<LittleEndian>()?,
+            self.read_i16::<LittleEndian>()?,
+            self.read_i16::<LittleEndian>()?,
+        ))
+    }
 }
 
 pub fn from_cstring(data: &[u8]) -> Result<String, str::Utf8Error> {